@@ -0,0 +1,44 @@
+#![no_main]
+
+use hssp2::{create, entry_reader, metadata, verify_detailed, File, FileWithSource};
+use libfuzzer_sys::fuzz_target;
+
+// The extension section is only parsed once the header announces it, so the
+// input is appended to a valid v3 archive as its section.
+fuzz_target!(|section: &[u8]| {
+    let mut target = dh::data::rw_empty();
+    let file = File {
+        path: "a.txt".to_string(),
+        directory: false,
+        offset: 0,
+        length: 5,
+    };
+    create(
+        3,
+        vec![FileWithSource(&file, &mut dh::data::read_ref(b"Hello"))],
+        None,
+        None,
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    let mut archive = dh::data::close(target);
+
+    let offset = archive.len() as u64;
+    archive.extend_from_slice(section);
+    archive[96..100].copy_from_slice(b"HSSX");
+    archive[100..108].copy_from_slice(&offset.to_le_bytes());
+    archive[108..112].copy_from_slice(&(section.len() as u32).to_le_bytes());
+
+    let Ok(meta) = metadata(&mut dh::data::read_ref(&archive), None) else {
+        return;
+    };
+    let _ = verify_detailed(&mut dh::data::read_ref(&archive), &meta);
+
+    let body = meta.decompressed.as_deref().unwrap_or(&archive);
+    for file in &meta.files {
+        if let Ok(mut reader) = entry_reader(&mut dh::data::read_ref(body), file) {
+            let _ = std::io::copy(&mut reader, &mut std::io::sink());
+        }
+    }
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use hssp2::{entry_reader, metadata, verify_detailed, verify_integrity};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(meta) = metadata(&mut dh::data::read_ref(data), None) else {
+        return;
+    };
+    let _ = verify_integrity(&mut dh::data::read_ref(data), &meta);
+    let _ = verify_detailed(&mut dh::data::read_ref(data), &meta);
+
+    // Entries of archives with a compressed body point into the decompressed one.
+    let body = meta.decompressed.as_deref().unwrap_or(data);
+    for file in &meta.files {
+        // Compressed data can be invalid even if the metadata is consistent.
+        if let Ok(mut reader) = entry_reader(&mut dh::data::read_ref(body), file) {
+            let _ = std::io::copy(&mut reader, &mut std::io::sink());
+        }
+    }
+});
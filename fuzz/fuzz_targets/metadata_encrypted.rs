@@ -0,0 +1,28 @@
+#![no_main]
+
+use hssp2::{entry_reader, metadata, verify_detailed, verify_integrity};
+use libfuzzer_sys::fuzz_target;
+
+// The seed corpus samples are encrypted with this password.
+const PASSWORD: &str = "Password";
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(meta) = metadata(&mut dh::data::read_ref(data), Some(PASSWORD)) else {
+        return;
+    };
+    let _ = verify_integrity(&mut dh::data::read_ref(data), &meta);
+
+    let Some(encryption) = &meta.encryption else {
+        return;
+    };
+    if encryption.hash != encryption.hash_expected {
+        return;
+    }
+    let _ = verify_detailed(&mut dh::data::read_ref(data), &meta);
+    for file in &meta.files {
+        // Compressed data can be invalid even if the metadata is consistent.
+        if let Ok(mut reader) = entry_reader(&mut dh::data::read_ref(&encryption.decrypted), file) {
+            let _ = std::io::copy(&mut reader, &mut std::io::sink());
+        }
+    }
+});
@@ -0,0 +1,86 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use dh::recommended::*;
+use hssp2::{create, extract, metadata, verify_integrity, write_hash, File, FileWithSource};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+struct Entry {
+    path: String,
+    directory: bool,
+    data: Vec<u8>,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    version: u8,
+    entries: Vec<Entry>,
+    encryption: Option<(String, [u8; 16])>,
+    main_file: Option<u32>,
+}
+
+fuzz_target!(|input: Input| {
+    let version = input.version % 3 + 1;
+    let entries: Vec<Entry> = input
+        .entries
+        .into_iter()
+        // create rejects these paths, see tests/fuzz.rs
+        .filter(|e| !e.path.is_empty() && e.path.len() < u16::MAX as usize - 2)
+        .filter(|e| e.directory || !e.path.starts_with("//"))
+        .map(|e| Entry {
+            data: if e.directory { vec![] } else { e.data },
+            ..e
+        })
+        .collect();
+    // create rejects main files that are out of range or directories.
+    let main_file = input
+        .main_file
+        .filter(|&i| entries.get(i as usize).is_some_and(|e| !e.directory));
+    let encryption = input
+        .encryption
+        .as_ref()
+        .map(|(password, iv)| (password.as_str(), iv));
+
+    let files: Vec<File> = entries
+        .iter()
+        .map(|e| File {
+            path: e.path.clone(),
+            directory: e.directory,
+            offset: 0,
+            length: e.data.len() as u64,
+        })
+        .collect();
+    let mut readers: Vec<_> = entries
+        .iter()
+        .map(|e| dh::data::read_ref(&e.data))
+        .collect();
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| FileWithSource(file, reader))
+        .collect();
+
+    let mut target = dh::data::rw_empty();
+    let result = create(version, sources, encryption, main_file, &mut target, 1024).unwrap();
+    write_hash(&mut target, result).unwrap();
+    let archive = dh::data::close(target);
+
+    let meta = metadata(&mut dh::data::read_ref(&archive), encryption.map(|e| e.0)).unwrap();
+    assert!(verify_integrity(&mut dh::data::read_ref(&archive), &meta).unwrap());
+    assert_eq!(meta.files.len(), entries.len());
+    assert_eq!(meta.main_file, main_file);
+
+    let body = match &meta.encryption {
+        Some(encryption) => encryption.decrypted.clone(),
+        None => archive,
+    };
+    for (file, entry) in meta.files.iter().zip(&entries) {
+        assert_eq!(file.path, entry.path);
+        assert_eq!(file.directory, entry.directory);
+
+        let mut target = dh::data::write_new(file.length);
+        extract(&mut dh::data::read_ref(&body), file, &mut target, 1024, 0).unwrap();
+        assert_eq!(dh::data::close(target), entry.data);
+    }
+});
@@ -0,0 +1,179 @@
+use crate::{
+    checksum, create,
+    extension::{self, Section},
+    metadata::detect_version,
+    ArchiveError, Compression, EntryWithSource, Limits, Link,
+};
+use dh::{recommended::*, Readable, Rw, Source};
+use std::io::{Error, ErrorKind, Result};
+
+/// Adds entries to the end of an existing unencrypted archive.
+///
+/// Only the new entries are written, together with the file count, the
+/// checksum and, for version 3, the extension section that follows the body.
+/// The archive is read once to check its checksum, as an outdated checksum
+/// would otherwise be replaced by one that covers the damage.
+///
+/// The rewritten section can be shorter than the old one, so `target` has to
+/// be a file or a vector that is truncated to the new end of the archive.
+pub fn append<'a>(
+    target: &'a mut dyn Rw<'a>,
+    sources: Vec<EntryWithSource<'a>>,
+    buffer_size: u64,
+) -> Result<()> {
+    let version = detect_version(Readable::as_trait(target))?;
+    let header_size = checksum::header_size(version);
+    let size = target.size()?;
+    if size < header_size {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Archive is too short to contain a header",
+        ));
+    }
+    if target.read_bytes_at(12, 48)?.iter().any(|&b| b != 0) {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "Appending to encrypted archives is not supported",
+        ));
+    }
+    if !matches!(target.rw_source(), Source::File(_) | Source::Vec(_)) {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "Appending requires a file or a vector as target",
+        ));
+    }
+    if checksum::compute(Readable::as_trait(target), header_size, size - header_size)?
+        != target.read_u32le_at(4)?
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Archive checksum does not match",
+        ));
+    }
+
+    let old_count = target.read_u32le_at(8)?;
+    let file_count = match old_count.checked_add(sources.len() as u32) {
+        Some(count) if sources.len() <= u32::MAX as usize => count,
+        _ => return Err(ArchiveError::TooManyEntries.into()),
+    };
+    for source in &sources {
+        create::validate(source.0)?;
+    }
+
+    let marker = if version == 3 {
+        extension::read_marker(Readable::as_trait(target))?
+    } else {
+        None
+    };
+    let mut section = match marker {
+        Some((offset, length)) => Some(extension::read(
+            Readable::as_trait(target),
+            offset,
+            length,
+            &Limits::default(),
+        )?),
+        None => None,
+    };
+    if section
+        .as_ref()
+        .is_some_and(|s| s.extensions.body_compression != Compression::None)
+    {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "Appending to archives with a compressed body is not supported",
+        ));
+    }
+    let entry_checksums = section.as_ref().is_some_and(|s| s.checksums.is_some());
+
+    // Compressed entries, attributes and links have to be recorded in the
+    // extension section, which is added if the archive does not have one yet.
+    if sources
+        .iter()
+        .any(|source| source.0.link.is_none() && source.0.compression != Compression::None)
+    {
+        if version < 3 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Compression requires HSSP version 3",
+            ));
+        }
+        section
+            .get_or_insert_with(Section::default)
+            .compression
+            .get_or_insert_with(|| vec![(Compression::None, 0); old_count as usize]);
+    }
+
+    if sources.iter().any(|source| source.0.attributes.is_some()) {
+        if version < 3 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Attributes require HSSP version 3",
+            ));
+        }
+        section
+            .get_or_insert_with(Section::default)
+            .attributes
+            .get_or_insert_with(|| vec![None; old_count as usize]);
+    }
+
+    if sources.iter().any(|source| source.0.link.is_some()) {
+        if version < 3 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Links require HSSP version 3",
+            ));
+        }
+        section
+            .get_or_insert_with(Section::default)
+            .links
+            .get_or_insert_with(Vec::new);
+    }
+
+    target.to(marker.map_or(size, |(offset, _)| offset))?;
+    for (i, source) in (old_count..).zip(sources) {
+        let file = source.0;
+        let checksum = create::write_entry(
+            target,
+            file,
+            source.1,
+            entry_checksums,
+            file.compression,
+            buffer_size,
+        )?;
+        if let Some(section) = &mut section {
+            if let Some(checksums) = &mut section.checksums {
+                checksums.extend(checksum);
+            }
+            if let Some(compression) = &mut section.compression {
+                compression.push(match &file.link {
+                    Some(link) => (Compression::None, link.target().len() as u64),
+                    None => (file.compression, file.length),
+                });
+            }
+            if let Some(attributes) = &mut section.attributes {
+                attributes.push(file.attributes);
+            }
+            if let (Some(links), Some(link)) = (&mut section.links, &file.link) {
+                links.push((i, matches!(link, Link::Hard(_))));
+            }
+        }
+    }
+
+    if let Some(section) = section {
+        extension::write(target, &section)?;
+    }
+
+    let end = target.pos()?;
+    if end < size {
+        match target.rw_source() {
+            Source::File(file) => file.set_len(end)?,
+            Source::Vec(data) => data.truncate(end as usize),
+            _ => unreachable!(),
+        }
+    }
+
+    let body_size = end - header_size;
+    target.write_u32le_at(8, file_count)?;
+    let hash = checksum::compute(Readable::as_trait(target), header_size, body_size)?;
+    target.write_u32le_at(4, hash)
+}
@@ -0,0 +1,44 @@
+use dh::Readable;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{cmp::min, io::Result};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const CHUNK_SIZE: u64 = 65536;
+
+// Authenticates the header (with the checksum field zeroed, as the checksum
+// also covers the tag), the ciphertext and the extension section up to the
+// tag, which is its last record.
+fn mac(reader: &mut dyn Readable, key: &[u8; 32], tag_pos: u64) -> Result<HmacSha256> {
+    let mut subkey = HmacSha256::new_from_slice(key).unwrap();
+    subkey.update(b"HSSP authentication");
+    let subkey = subkey.finalize().into_bytes();
+
+    let mut mac = HmacSha256::new_from_slice(&subkey).unwrap();
+    let mut header = reader.read_bytes_at(0, 128)?;
+    header[4..8].fill(0);
+    mac.update(&header);
+
+    let mut pos = 128;
+    while pos < tag_pos {
+        let chunk = min(CHUNK_SIZE, tag_pos - pos);
+        mac.update(&reader.read_bytes_at(pos, chunk)?);
+        pos += chunk;
+    }
+
+    Ok(mac)
+}
+
+pub fn compute(reader: &mut dyn Readable, key: &[u8; 32], tag_pos: u64) -> Result<[u8; 32]> {
+    Ok(mac(reader, key, tag_pos)?.finalize().into_bytes().into())
+}
+
+pub fn verify(
+    reader: &mut dyn Readable,
+    key: &[u8; 32],
+    tag_pos: u64,
+    tag: &[u8; 32],
+) -> Result<bool> {
+    Ok(mac(reader, key, tag_pos)?.verify_slice(tag).is_ok())
+}
@@ -0,0 +1,78 @@
+use hssp2::{metadata, run};
+use std::{env, io::Result, process::ExitCode};
+
+const USAGE: &str = "Usage: hssp run [--password <password>] <archive> [-- <args>...]";
+
+/// The arguments of `hssp run`.
+struct RunArgs<'a> {
+    password: Option<&'a str>,
+    archive: &'a str,
+    args: Vec<&'a str>,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let run_args = match parse(&args) {
+        Ok(run_args) => run_args,
+        Err(message) => {
+            eprintln!("hssp: {}\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    match run_archive(&run_args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("hssp: {}: {}", run_args.archive, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse(args: &[String]) -> std::result::Result<RunArgs<'_>, String> {
+    let mut rest = match args.split_first() {
+        Some((command, rest)) if command == "run" => rest,
+        Some((command, _)) => return Err(format!("Unknown command {:?}", command)),
+        None => return Err("Missing command".to_string()),
+    };
+
+    let mut password = None;
+    let archive = loop {
+        match rest {
+            [option, value, tail @ ..] if option == "--password" => {
+                password = Some(value.as_str());
+                rest = tail;
+            }
+            [option, ..] if option.starts_with("--") => {
+                return Err(format!("Unknown option {:?}", option))
+            }
+            [archive, tail @ ..] => {
+                rest = tail;
+                break archive.as_str();
+            }
+            [] => return Err("Missing archive".to_string()),
+        }
+    };
+    let args = match rest {
+        [separator, tail @ ..] if separator == "--" => tail.iter().map(String::as_str).collect(),
+        [] => Vec::new(),
+        [argument, ..] => return Err(format!("Unexpected argument {:?}", argument)),
+    };
+
+    Ok(RunArgs {
+        password,
+        archive,
+        args,
+    })
+}
+
+/// Runs the main file of an archive and exits with its exit code.
+fn run_archive(run_args: &RunArgs) -> Result<ExitCode> {
+    let mut reader = dh::file::open_r(run_args.archive)?;
+    let meta = metadata(&mut reader, run_args.password)?;
+    let status = run(&mut reader, &meta, &run_args.args, 64 * 1024)?;
+    // Processes ended by a signal have no exit code.
+    Ok(match status.code() {
+        Some(code) => ExitCode::from(code as u8),
+        None => ExitCode::FAILURE,
+    })
+}
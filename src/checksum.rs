@@ -0,0 +1,164 @@
+use crate::metadata::detect_version;
+use dh::{recommended::*, Readable, Rw};
+use std::io::{Error, ErrorKind, Result};
+
+const SEED: u32 = 0x31082007;
+const BUFFER_SIZE: u64 = 65536;
+
+/// The size of the header, which is not covered by the checksum.
+pub fn header_size(version: u8) -> u64 {
+    if version > 2 {
+        128
+    } else {
+        64
+    }
+}
+
+/// Calculates the archive checksum over `size` bytes starting at `offset`.
+pub fn compute(reader: &mut dyn Readable, offset: u64, size: u64) -> Result<u32> {
+    Ok(compute_with_progress(reader, offset, size, BUFFER_SIZE, &mut |_| true)?.unwrap())
+}
+
+/// Calculates the checksum of data that is already in memory.
+#[cfg(feature = "mmap")]
+pub fn compute_bytes(data: &[u8]) -> u32 {
+    let mut hasher = Murmur3::new(SEED);
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Like [`compute`], but reads `chunk_size` bytes at a time and calls
+/// `progress` with the number of bytes processed so far after every chunk.
+///
+/// Returns `None` as soon as `progress` returns `false`.
+pub fn compute_with_progress(
+    reader: &mut dyn Readable,
+    offset: u64,
+    size: u64,
+    chunk_size: u64,
+    progress: &mut dyn FnMut(u64) -> bool,
+) -> Result<Option<u32>> {
+    if chunk_size == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Chunk size must not be zero",
+        ));
+    }
+
+    let pos_before = reader.pos()?;
+    let mut hasher = Murmur3::new(SEED);
+    let mut processed = 0;
+    while processed < size {
+        let chunk = reader.read_bytes_at(offset + processed, chunk_size.min(size - processed))?;
+        hasher.update(&chunk);
+        processed += chunk.len() as u64;
+        if !progress(processed) {
+            reader.to(pos_before)?;
+            return Ok(None);
+        }
+    }
+    reader.to(pos_before)?;
+
+    Ok(Some(hasher.finish()))
+}
+
+/// Incremental 32 bit MurmurHash3.
+///
+/// Like the `murmur3` crate the checksum used to be calculated with, the
+/// length is mixed in as a wrapping 32 bit value.
+struct Murmur3 {
+    state: u32,
+    length: u32,
+    tail: [u8; 4],
+    tail_length: usize,
+}
+
+impl Murmur3 {
+    fn new(seed: u32) -> Self {
+        Self {
+            state: seed,
+            length: 0,
+            tail: [0; 4],
+            tail_length: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u32);
+
+        if self.tail_length > 0 {
+            let take = data.len().min(4 - self.tail_length);
+            self.tail[self.tail_length..self.tail_length + take].copy_from_slice(&data[..take]);
+            self.tail_length += take;
+            data = &data[take..];
+            if self.tail_length < 4 {
+                return;
+            }
+            self.mix(u32::from_le_bytes(self.tail));
+            self.tail_length = 0;
+        }
+
+        let mut blocks = data.chunks_exact(4);
+        for block in &mut blocks {
+            self.mix(u32::from_le_bytes(block.try_into().unwrap()));
+        }
+        let rest = blocks.remainder();
+        self.tail[..rest.len()].copy_from_slice(rest);
+        self.tail_length = rest.len();
+    }
+
+    fn mix(&mut self, k: u32) {
+        self.state ^= scramble(k);
+        self.state = self.state.rotate_left(13);
+        self.state = self.state.wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    fn finish(&self) -> u32 {
+        let mut hash = self.state;
+        if self.tail_length > 0 {
+            let mut k = 0;
+            for (i, byte) in self.tail[..self.tail_length].iter().enumerate() {
+                k |= (*byte as u32) << (8 * i);
+            }
+            hash ^= scramble(k);
+        }
+
+        hash ^= self.length;
+        hash ^= hash >> 16;
+        hash = hash.wrapping_mul(0x85eb_ca6b);
+        hash ^= hash >> 13;
+        hash = hash.wrapping_mul(0xc2b2_ae35);
+        hash ^= hash >> 16;
+        hash
+    }
+}
+
+fn scramble(k: u32) -> u32 {
+    k.wrapping_mul(0xcc9e_2d51)
+        .rotate_left(15)
+        .wrapping_mul(0x1b87_3593)
+}
+
+/// Recalculates the checksum of an archive and writes it to the header.
+///
+/// Returns the old and the new checksum. With `dry_run`, the header is left
+/// untouched, so this only reports whether the stored checksum is stale.
+pub fn rehash(target: &mut dyn Rw, dry_run: bool) -> Result<(u32, u32)> {
+    let version = detect_version(Readable::as_trait(target))?;
+    let offset = header_size(version);
+    let size = target.size()?;
+    if size < offset {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Archive is too short to contain a header",
+        ));
+    }
+
+    let old = target.read_u32le_at(4)?;
+    let new = compute(Readable::as_trait(target), offset, size - offset)?;
+    if !dry_run && old != new {
+        target.write_u32le_at(4, new)?;
+    }
+
+    Ok((old, new))
+}
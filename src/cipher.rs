@@ -0,0 +1,41 @@
+use crate::ArchiveError;
+use dh::Readable;
+use libaes::Cipher;
+use std::io::Result;
+
+const BLOCK_SIZE: usize = 16;
+
+/// Decrypts an AES-256-CBC body, validating its length and PKCS#7 padding.
+pub fn decrypt(
+    reader: &mut dyn Readable,
+    key: &[u8; 32],
+    iv: &[u8; 16],
+    offset: u64,
+    size: u64,
+) -> Result<Vec<u8>> {
+    if size == 0 || !size.is_multiple_of(BLOCK_SIZE as u64) {
+        return Err(ArchiveError::TruncatedCiphertext.into());
+    }
+
+    let mut cipher = Cipher::new_256(key);
+    cipher.set_auto_padding(false);
+    let mut data = cipher.cbc_decrypt(iv, &reader.read_bytes_at(offset, size)?);
+
+    let padding = data[data.len() - 1] as usize;
+    if padding == 0
+        || padding > BLOCK_SIZE
+        || data[data.len() - padding..]
+            .iter()
+            .any(|&b| b as usize != padding)
+    {
+        return Err(ArchiveError::BadPadding.into());
+    }
+    data.truncate(data.len() - padding);
+
+    Ok(data)
+}
+
+/// Encrypts a body with AES-256-CBC and PKCS#7 padding.
+pub fn encrypt(data: &[u8], key: &[u8; 32], iv: &[u8; 16]) -> Vec<u8> {
+    Cipher::new_256(key).cbc_encrypt(iv, data)
+}
@@ -0,0 +1,150 @@
+use crate::{Compression, Entry};
+use dh::{recommended::*, Readable};
+use std::io::{copy, Error, ErrorKind, Read, Result, Write};
+
+pub fn id(compression: Compression) -> u8 {
+    match compression {
+        Compression::None => 0,
+        Compression::Deflate => 1,
+        Compression::Zstd => 2,
+        Compression::Lzma => 3,
+    }
+}
+
+pub fn from_id(id: u8) -> Result<Compression> {
+    match id {
+        0 => Ok(Compression::None),
+        1 => Ok(Compression::Deflate),
+        2 => Ok(Compression::Zstd),
+        3 => Ok(Compression::Lzma),
+        id => Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("Unsupported compression {}", id),
+        )),
+    }
+}
+
+/// Compresses everything `reader` yields into `target` and returns the number
+/// of bytes read.
+pub fn compress(
+    compression: Compression,
+    reader: &mut dyn Read,
+    target: &mut dyn Write,
+) -> Result<u64> {
+    Ok(match compression {
+        Compression::None => copy(reader, target)?,
+        #[cfg(feature = "deflate")]
+        Compression::Deflate => {
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(target, flate2::Compression::default());
+            let read = copy(reader, &mut encoder)?;
+            encoder.finish()?;
+            read
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            let mut encoder = zstd::stream::write::Encoder::new(target, 0)?;
+            let read = copy(reader, &mut encoder)?;
+            encoder.finish()?;
+            read
+        }
+        #[cfg(feature = "lzma")]
+        Compression::Lzma => {
+            let mut encoder = xz2::write::XzEncoder::new(target, 6);
+            let read = copy(reader, &mut encoder)?;
+            encoder.finish()?;
+            read
+        }
+        #[allow(unreachable_patterns)]
+        compression => return Err(disabled(compression)),
+    })
+}
+
+/// Returns a reader yielding the uncompressed data of an entry.
+///
+/// `source` is read from the entry's offset on, for encrypted archives it has
+/// to read from [`Encryption::decrypted`](crate::Encryption::decrypted) and for
+/// archives with a compressed body from [`Metadata::decompressed`](crate::Metadata::decompressed).
+/// Reading fails if the data does not decompress to [`Entry::uncompressed_length`].
+pub fn entry_reader<'r>(source: &'r mut dyn Readable, file: &Entry) -> Result<Box<dyn Read + 'r>> {
+    source.to(file.offset)?;
+    let stored = Read::take(source, file.length);
+    if file.compression == Compression::None {
+        return Ok(Box::new(stored));
+    }
+    decoder(file.compression, stored, file.uncompressed_length)
+}
+
+/// Decompresses a whole body, which has to decompress to `length` bytes.
+pub fn decompress(compression: Compression, stored: &[u8], length: u64) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    decoder(compression, stored, length)?.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+fn decoder<'r>(
+    compression: Compression,
+    stored: impl Read + 'r,
+    length: u64,
+) -> Result<Box<dyn Read + 'r>> {
+    let decoder: Box<dyn Read + 'r> = match compression {
+        Compression::None => Box::new(stored),
+        #[cfg(feature = "deflate")]
+        Compression::Deflate => Box::new(flate2::read::DeflateDecoder::new(stored)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(stored)?),
+        #[cfg(feature = "lzma")]
+        Compression::Lzma => Box::new(xz2::read::XzDecoder::new(stored)),
+        #[allow(unreachable_patterns)]
+        compression => return Err(disabled(compression)),
+    };
+
+    Ok(Box::new(Exact {
+        inner: decoder,
+        remaining: length,
+    }))
+}
+
+/// The error for an algorithm whose Cargo feature is disabled.
+fn disabled(compression: Compression) -> Error {
+    let feature = match compression {
+        Compression::Deflate => "deflate",
+        Compression::Zstd => "zstd",
+        Compression::Lzma => "lzma",
+        Compression::None => unreachable!("Stored data needs no feature"),
+    };
+    Error::new(
+        ErrorKind::Unsupported,
+        format!(
+            "{:?} compression requires the {} feature",
+            compression, feature
+        ),
+    )
+}
+
+/// Fails if the inner reader yields more or less than `remaining` bytes.
+struct Exact<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for Exact<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // One byte more than expected is enough to detect oversized data.
+        let max = buf
+            .len()
+            .min(self.remaining.saturating_add(1).min(usize::MAX as u64) as usize);
+        let read = self.inner.read(&mut buf[..max])?;
+        if read as u64 > self.remaining || (read == 0 && self.remaining > 0) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Data does not decompress to its recorded length",
+            ));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
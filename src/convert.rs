@@ -0,0 +1,143 @@
+use crate::{
+    auth, checksum, cipher, create, extension, key, metadata::detect_version, ArchiveError,
+    Compression, Kdf, Limits,
+};
+use dh::{recommended::*, Readable, Rw, Writable};
+use std::io::{Error, ErrorKind, Result};
+
+/// Writes a copy of an archive in another HSSP version.
+///
+/// Only the header is rewritten, the body is copied as is. Encrypted archives
+/// only have to be decrypted, which requires `password`, if their key derivation
+/// function cannot be stored in the target version. Everything else in the
+/// extension section is dropped when converting to version 1 or 2. Like
+/// [`create`](crate::create), this returns the checksum to be written with
+/// [`write_hash`](crate::write_hash).
+pub fn convert<'a>(
+    source: &'a mut dyn Readable<'a>,
+    password: Option<&str>,
+    version: u8,
+    target: &'a mut dyn Rw<'a>,
+    buffer_size: u64,
+) -> Result<(u64, u32)> {
+    if !(1..=3).contains(&version) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Unsupported HSSP version {}", version),
+        ));
+    }
+
+    let source_version = detect_version(source)?;
+    let header_size = checksum::header_size(source_version);
+    if source.size()? < header_size {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Archive is too short to contain a header",
+        ));
+    }
+    let file_count = source.read_u32le_at(8)?;
+    let mut pwd_hash: [u8; 32] = source.read_bytes_at(12, 32)?.try_into().unwrap();
+    let iv: [u8; 16] = source.read_bytes_at(44, 16)?.try_into().unwrap();
+    let main = source.read_u32le_at(60)?;
+    let encrypted = !(pwd_hash == [0; 32] && iv == [0; 16]);
+
+    let marker = if source_version == 3 {
+        extension::read_marker(source)?
+    } else {
+        None
+    };
+    let section = match marker {
+        Some((offset, length)) => {
+            Some(extension::read(source, offset, length, &Limits::default())?)
+        }
+        None => None,
+    };
+    let body_end = match marker {
+        Some((offset, _)) => offset,
+        None => source.size()?,
+    };
+    let kdf = section
+        .as_ref()
+        .map(|s| s.extensions.kdf)
+        .unwrap_or_default();
+
+    if version < 3
+        && section.as_ref().is_some_and(|s| {
+            s.compression.is_some() || s.extensions.body_compression != Compression::None
+        })
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Compression requires HSSP version 3",
+        ));
+    }
+    if version < 3 && section.as_ref().is_some_and(|s| s.duplicates.is_some()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Deduplication requires HSSP version 3",
+        ));
+    }
+
+    // Legacy versions always derive the key with SHA-256.
+    let mut reencrypted = None;
+    if encrypted && version < 3 && kdf != Kdf::Sha256 {
+        let Some(password) = password else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Converting this archive requires its password",
+            ));
+        };
+        let key = key::derive(password, &kdf)?;
+        if key::hash(&key)? != pwd_hash {
+            return Err(Error::new(ErrorKind::InvalidInput, "Wrong password"));
+        }
+        if let Some((mac, mac_pos)) = section.as_ref().and_then(|s| s.mac) {
+            if !auth::verify(source, &key, mac_pos, &mac)? {
+                return Err(ArchiveError::AuthenticationFailed.into());
+            }
+        }
+
+        let body = cipher::decrypt(source, &key, &iv, header_size, body_end - header_size)?;
+        let key = key::derive(password, &Kdf::Sha256)?;
+        pwd_hash = key::hash(&key)?;
+        reencrypted = Some(cipher::encrypt(&body, &key, &iv));
+    }
+
+    target.write_bytes(if version == 1 { b"SFA\0" } else { b"HSSP" })?;
+    let hash_pos = target.pos()?;
+    target.write_u32le(0)?;
+    target.write_u32le(file_count)?;
+    target.write_bytes(&pwd_hash)?;
+    target.write_bytes(&iv)?;
+    target.write_u32le(main)?;
+    if version > 2 {
+        target.write_bytes(&[0; 64])?;
+    }
+
+    let body_pos = target.pos()?;
+    match reencrypted {
+        Some(body) => target.write_bytes(&body)?,
+        None => create::copy(
+            source,
+            header_size,
+            body_end - header_size,
+            target,
+            buffer_size,
+        )?,
+    }
+
+    // The MAC covers the header, which is the same in every v3 archive with
+    // the same contents, so the section stays valid.
+    if let Some((offset, length)) = marker.filter(|_| version == 3) {
+        let new_offset = target.pos()?;
+        extension::write_marker(Writable::as_trait(target), new_offset, length)?;
+        let section = source.read_bytes_at(offset, length as u64)?;
+        extension::write_guard(Writable::as_trait(target), &section)?;
+        target.write_bytes(&section)?;
+    }
+
+    let body_size = target.pos()? - body_pos;
+    let hash = checksum::compute(Readable::as_trait(target), body_pos, body_size)?;
+
+    Ok((hash_pos, hash))
+}
@@ -1,10 +1,15 @@
-use crate::FileWithSource;
-use acr::{
-    encryption::aes256cbc,
-    hash::{murmur3, sha256},
+use crate::{
+    auth, checksum, cipher, compression,
+    extension::{self, Section},
+    key, Compression, Entry, EntryWithSource, Extensions, File, FileWithSource, Link,
 };
+use acr::hash::crc32;
 use dh::{recommended::*, Readable, Rw, Writable};
-use std::io::Result;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Read, Result},
+};
 
 pub fn create<'a>(
     version: u8,
@@ -14,104 +19,556 @@ pub fn create<'a>(
     target: &'a mut dyn Rw<'a>,
     buffer_size: u64,
 ) -> Result<(u64, u32)> {
+    let mut files = Vec::new();
+    let mut readers = Vec::new();
+    for source in sources {
+        files.push(Entry::from(source.0.clone()));
+        readers.push(source.1);
+    }
+    let entries: Vec<_> = files.iter().zip(0..).collect();
+
+    write(
+        version,
+        &entries,
+        &mut readers,
+        false,
+        encryption,
+        main_file,
+        None,
+        target,
+        buffer_size,
+    )
+}
+
+/// Like [`create`], but writes an extension section announced in the v3 header.
+///
+/// Extensions, as well as entries with compression, attributes or links, are
+/// only supported by version 3 archives.
+pub fn create_extended<'a>(
+    version: u8,
+    sources: Vec<EntryWithSource<'a>>,
+    encryption: Option<(&str, &[u8; 16])>,
+    main_file: Option<u32>,
+    extensions: Option<&Extensions>,
+    target: &'a mut dyn Rw<'a>,
+    buffer_size: u64,
+) -> Result<(u64, u32)> {
+    let mut entries = Vec::new();
+    let mut readers = Vec::new();
+    for (i, source) in sources.into_iter().enumerate() {
+        entries.push((source.0, i));
+        readers.push(source.1);
+    }
+
+    write(
+        version,
+        &entries,
+        &mut readers,
+        false,
+        encryption,
+        main_file,
+        extensions,
+        target,
+        buffer_size,
+    )
+}
+
+/// Writes an archive whose entries are copied from `readers`.
+///
+/// Each entry is paired with the index of the reader its data is read from,
+/// so several entries can share one reader, e.g. another archive. With
+/// `stored`, the entries are read from another archive, so compressed data is
+/// copied as is instead of being compressed again.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn write(
+    version: u8,
+    entries: &[(&Entry, usize)],
+    readers: &mut [&mut dyn Readable],
+    stored: bool,
+    encryption: Option<(&str, &[u8; 16])>,
+    main_file: Option<u32>,
+    extensions: Option<&Extensions>,
+    target: &mut dyn Rw,
+    buffer_size: u64,
+) -> Result<(u64, u32)> {
+    write_sources(
+        version,
+        entries,
+        readers,
+        stored,
+        encryption,
+        main_file,
+        extensions,
+        target,
+        buffer_size,
+    )
+}
+
+/// Hands out the reader of every source index passed to [`write_sources`].
+pub(crate) trait Sources<'a> {
+    fn reader(&mut self, source: usize) -> Result<&mut dyn Readable<'a>>;
+}
+
+impl<'a> Sources<'a> for [&mut dyn Readable<'a>] {
+    fn reader(&mut self, source: usize) -> Result<&mut dyn Readable<'a>> {
+        Ok(&mut *self[source])
+    }
+}
+
+/// Like [`write`], but the readers are requested from `sources` as each
+/// entry is written, so they do not all have to be open at once.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn write_sources<'a>(
+    version: u8,
+    entries: &[(&Entry, usize)],
+    sources: &mut (impl Sources<'a> + ?Sized),
+    stored: bool,
+    encryption: Option<(&str, &[u8; 16])>,
+    main_file: Option<u32>,
+    extensions: Option<&Extensions>,
+    target: &mut dyn Rw,
+    buffer_size: u64,
+) -> Result<(u64, u32)> {
+    if extensions.is_some() && version < 3 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Extensions require HSSP version 3",
+        ));
+    }
+
+    for (file, _) in entries {
+        validate(file)?;
+    }
+    if let Some(main_file) = main_file {
+        validate_main_file(
+            main_file,
+            entries.get(main_file as usize).map(|(file, _)| *file),
+            entries.len(),
+            ErrorKind::InvalidInput,
+        )?;
+    }
+
+    let default_compression = extensions.map(|e| e.compression).unwrap_or_default();
+    let compression: Vec<_> = entries
+        .iter()
+        .map(|(file, _)| {
+            if let Some(link) = &file.link {
+                (Compression::None, link.target().len() as u64)
+            } else if stored || file.compression != Compression::None {
+                (
+                    file.compression,
+                    if stored {
+                        file.uncompressed_length
+                    } else {
+                        file.length
+                    },
+                )
+            } else {
+                (default_compression, file.length)
+            }
+        })
+        .collect();
+    let compressed = compression.iter().any(|(c, _)| *c != Compression::None);
+    if compressed && version < 3 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Compression requires HSSP version 3",
+        ));
+    }
+    let attributes: Vec<_> = entries.iter().map(|(file, _)| file.attributes).collect();
+    let has_attributes = attributes.iter().any(Option::is_some);
+    if has_attributes && version < 3 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Attributes require HSSP version 3",
+        ));
+    }
+    let links: Vec<_> = entries
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (file, _))| {
+            let link = file.link.as_ref()?;
+            Some((i as u32, matches!(link, Link::Hard(_))))
+        })
+        .collect();
+    if !links.is_empty() && version < 3 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Links require HSSP version 3",
+        ));
+    }
+    // Compressed entries, attributes and links are recorded in the extension
+    // section.
+    let default_extensions = Extensions::default();
+    let extensions = extensions
+        .or((compressed || has_attributes || !links.is_empty()).then_some(&default_extensions));
+
     let encrypted = encryption.is_some();
-    let key = if encrypted {
-        sha256(
-            &mut dh::data::read_ref(encryption.unwrap().0.as_bytes()),
-            0,
-            encryption.unwrap().0.len() as u64,
-        )?
-    } else {
-        [0; 32]
-    };
-    let key_hash = if encrypted {
-        sha256(&mut dh::data::read_ref(&key), 0, 32)?
+    if extensions.is_some_and(|e| e.authenticated) && !encrypted {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Authentication requires encryption",
+        ));
+    }
+    let kdf = extensions.map(|e| e.kdf).unwrap_or_default();
+    let (key, key_hash, iv) = if let Some((password, iv)) = encryption {
+        let key = key::derive(password, &kdf)?;
+        (key, key::hash(&key)?, iv)
     } else {
-        [0; 32]
+        ([0; 32], [0; 32], &[0; 16])
     };
-    let iv = if encrypted {
-        encryption.unwrap().1
-    } else {
-        &[0; 16]
+
+    let hash_pos = write_header(
+        target,
+        version,
+        entries.len() as u32,
+        &key_hash,
+        iv,
+        main_file,
+    )?;
+
+    let body_pos = target.pos()?;
+    let entry_checksums = extensions.is_some_and(|e| e.entry_checksums);
+    let mut checksums = Vec::new();
+
+    let body_compression = extensions.map(|e| e.body_compression).unwrap_or_default();
+    let mut body_length = 0;
+    let mut buffer = (encrypted || body_compression != Compression::None).then(dh::data::rw_empty);
+    let body: &mut dyn Rw = match &mut buffer {
+        Some(buffer) => buffer,
+        None => target,
     };
+    let deduplicate = extensions.is_some_and(|e| e.deduplicate);
+    let mut duplicates = Vec::new();
+    let mut originals = HashMap::new();
+    for (i, ((file, source), (compression, _))) in entries.iter().zip(&compression).enumerate() {
+        let reader = sources.reader(*source)?;
+        let hash = if deduplicate && !file.directory && file.link.is_none() && file.length > 0 {
+            Some((
+                compression::id(*compression),
+                content_hash(reader, file.offset, file.length, buffer_size)?,
+            ))
+        } else {
+            None
+        };
+
+        // Duplicates are written without data, their checksum is the one of
+        // the entry holding it.
+        if let Some(&(original, checksum)) = hash.as_ref().and_then(|h| originals.get(h)) {
+            let padding = write_entry_header(
+                body,
+                &File {
+                    path: file.path.clone(),
+                    ..Default::default()
+                },
+            )?;
+            body.write_bytes(&vec![0; padding])?;
+            checksums.extend(checksum);
+            duplicates.push((i as u32, original));
+            continue;
+        }
+
+        let checksum = write_entry(
+            body,
+            file,
+            reader,
+            entry_checksums,
+            if stored {
+                Compression::None
+            } else {
+                *compression
+            },
+            buffer_size,
+        )?;
+        checksums.extend(checksum);
+        if let Some(hash) = hash {
+            originals.insert(hash, (i as u32, checksum));
+        }
+    }
+
+    // Checksums of plaintext would reveal something about the data of an
+    // encrypted archive, so they are encrypted along with it.
+    let body_checksums = entry_checksums && encrypted;
+    if body_checksums {
+        for checksum in &checksums {
+            body.write_u32le(*checksum)?;
+        }
+    }
+
+    if let Some(buffer) = buffer {
+        let mut body = dh::data::close(buffer);
+        // The body is compressed first, as ciphertext does not compress.
+        if body_compression != Compression::None {
+            body_length = body.len() as u64;
+            let mut compressed = Vec::new();
+            compression::compress(body_compression, &mut body.as_slice(), &mut compressed)?;
+            body = compressed;
+        }
+        if encrypted {
+            body = cipher::encrypt(&body, &key, iv);
+        }
+        target.write_bytes(&body)?;
+    }
+
+    if let Some(extensions) = extensions {
+        let offset = target.pos()?;
+        let section = extension::encode(
+            &Section {
+                extensions: extensions.clone(),
+                checksums: (entry_checksums && !encrypted).then_some(checksums),
+                body_checksums,
+                compression: compressed.then_some(compression),
+                body_length,
+                duplicates: deduplicate.then_some(duplicates),
+                attributes: has_attributes.then_some(attributes),
+                links: (!links.is_empty()).then_some(links),
+                ..Default::default()
+            },
+            encrypted,
+        )?;
+        extension::write_marker(Writable::as_trait(target), offset, section.len() as u32)?;
+        extension::write_guard(Writable::as_trait(target), &section)?;
+        target.write_bytes(&section)?;
+        if encrypted && extensions.authenticated {
+            let mac_pos = target.pos()? - 32;
+            let mac = auth::compute(Readable::as_trait(target), &key, mac_pos)?;
+            target.write_bytes_at(mac_pos, &mac)?;
+        }
+    }
+
+    let body_size = target.pos()? - body_pos;
+
+    let hash = checksum::compute(Readable::as_trait(target), body_pos, body_size)?;
+
+    Ok((hash_pos, hash))
+}
 
+/// Writes a header with a zeroed checksum and returns the checksum's position.
+///
+/// For unencrypted archives, `key_hash` and `iv` are all zeros.
+pub(crate) fn write_header(
+    target: &mut dyn Rw,
+    version: u8,
+    file_count: u32,
+    key_hash: &[u8; 32],
+    iv: &[u8; 16],
+    main_file: Option<u32>,
+) -> Result<u64> {
     target.write_bytes(if version == 1 { b"SFA\0" } else { b"HSSP" })?;
     let hash_pos = target.pos()?;
     target.write_u32le(0)?;
-    target.write_u32le(sources.len() as u32)?;
-    if encrypted {
-        target.write_bytes(&key_hash)?;
-        target.write_bytes(iv)?;
-    } else {
-        target.write_bytes(&[0; 48])?;
-    }
-    if main_file.is_some() {
-        let main_file = main_file.unwrap();
-        target.write_u32le(if main_file == (main_file % 4294967295) {
-            main_file + 1
-        } else {
-            0
-        })?;
-    } else {
-        target.write_u32le(0)?;
-    }
+    target.write_u32le(file_count)?;
+    target.write_bytes(key_hash)?;
+    target.write_bytes(iv)?;
+    // Stored as index + 1, as 0 means there is no main file. Callers check
+    // the index is in range, so it cannot overflow.
+    target.write_u32le(main_file.map_or(0, |main_file| main_file + 1))?;
 
     if version > 2 {
         target.write_bytes(&[0; 64])?;
     }
 
-    let body_pos = target.pos()?;
+    Ok(hash_pos)
+}
 
-    if encrypted {
-        let mut body = dh::data::rw_empty();
-        for source in sources {
-            let file = source.0;
-            let reader = source.1;
-            let path = if file.directory {
-                &(("//").to_string() + &file.path)
-            } else {
-                &file.path
-            };
-
-            body.write_u64le(file.length)?;
-            body.write_u16le(path.len() as u16)?;
-            body.write_utf8(path)?;
-            reader.copy_at(file.offset, file.length, &mut body, buffer_size)?;
-            body.write_bytes(&vec![0; path.len()])?;
+/// Returns the index of the regular file at `path` among `files`, to be
+/// used as the main file of an archive.
+pub fn find_main_file<'a>(files: impl IntoIterator<Item = &'a Entry>, path: &str) -> Result<u32> {
+    // A directory can share its path with a file, which takes precedence.
+    let mut found: Option<(usize, &Entry)> = None;
+    for (i, file) in files.into_iter().enumerate() {
+        if file.path == path && found.is_none_or(|(_, found)| found.directory) {
+            found = Some((i, file));
         }
+    }
+    let (index, file) = found.ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("Main file {:?} is not in the archive", path),
+        )
+    })?;
+    validate_main_file(index as u32, Some(file), index + 1, ErrorKind::InvalidInput)?;
+    Ok(index as u32)
+}
 
-        let body_size = body.size()?;
+/// Checks that the main file `file`, found at index `main_file` of `count`
+/// entries, is a regular file.
+pub(crate) fn validate_main_file(
+    main_file: u32,
+    file: Option<&Entry>,
+    count: usize,
+    kind: ErrorKind,
+) -> Result<()> {
+    let Some(file) = file else {
+        return Err(Error::new(
+            kind,
+            format!(
+                "Main file index {} is out of range for {} entries",
+                main_file, count
+            ),
+        ));
+    };
+    if file.directory || file.link.is_some() {
+        return Err(Error::new(
+            kind,
+            format!("Main file {:?} is not a regular file", file.path),
+        ));
+    }
+    Ok(())
+}
 
-        let cipher = aes256cbc::encrypt(&mut body, &key, iv, 0, body_size)?;
-        target.write_bytes(&cipher)?;
+/// Checks that an entry can be represented in an archive.
+pub(crate) fn validate(file: &Entry) -> Result<()> {
+    // An entry with an empty path can be all zeros, which would make a v2
+    // body look like the reserved bytes of a v3 header.
+    if file.path.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Paths must not be empty",
+        ));
+    }
+    if file.directory && file.link.is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Directory {:?} cannot be a link", file.path),
+        ));
+    }
+    // Directories are marked by a leading "//", so files cannot use it.
+    if !file.directory && file.path.starts_with("//") {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("File path {:?} must not start with //", file.path),
+        ));
+    }
+    if file.path.len() + if file.directory { 2 } else { 0 } > u16::MAX as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Path of {:?} is too long", file.path),
+        ));
+    }
+    Ok(())
+}
+
+/// Writes the size and path of an entry, which are followed by its data.
+///
+/// Returns the number of zero bytes that have to follow the data.
+pub(crate) fn write_entry_header(target: &mut dyn Rw, file: &File) -> Result<usize> {
+    let path = if file.directory {
+        &(("//").to_string() + &file.path)
     } else {
-        for source in sources {
-            let file = source.0;
-            let reader = source.1;
-            let path = if file.directory {
-                &(("//").to_string() + &file.path)
-            } else {
-                &file.path
-            };
-
-            target.write_u64le(file.length)?;
-            target.write_u16le(path.len() as u16)?;
-            target.write_utf8(path)?;
-            reader.copy_at(
-                file.offset,
-                file.length,
-                Writable::as_trait(target),
-                buffer_size,
-            )?;
-            target.write_bytes(&vec![0; path.len()])?;
-        }
+        &file.path
+    };
+
+    target.write_u64le(file.length)?;
+    target.write_u16le(path.len() as u16)?;
+    target.write_utf8(path)?;
+    Ok(path.len())
+}
+
+/// Writes a single entry and returns the CRC32 of its stored data if requested.
+///
+/// The data of links is their target instead of what `reader` holds.
+pub(crate) fn write_entry(
+    target: &mut dyn Rw,
+    file: &Entry,
+    reader: &mut dyn Readable,
+    checksum: bool,
+    compression: Compression,
+    buffer_size: u64,
+) -> Result<Option<u32>> {
+    if let Some(link) = &file.link {
+        let link_target = link.target().as_bytes();
+        return write_entry(
+            target,
+            &Entry::from(File {
+                path: file.path.clone(),
+                length: link_target.len() as u64,
+                ..Default::default()
+            }),
+            &mut dh::data::read_ref(link_target),
+            checksum,
+            Compression::None,
+            buffer_size,
+        );
     }
 
-    let body_size = target.pos()? - body_pos;
+    let entry_pos = target.pos()?;
+    let padding = write_entry_header(target, file)?;
+    let data_pos = target.pos()?;
+    let length = if compression == Compression::None {
+        copy(reader, file.offset, file.length, target, buffer_size)?;
+        file.length
+    } else {
+        let pos_before = reader.pos()?;
+        reader.to(file.offset)?;
+        let result = compression::compress(
+            compression,
+            &mut Read::take(&mut *reader, file.length),
+            target,
+        );
+        reader.to(pos_before)?;
+        if result? != file.length {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Data of {:?} is shorter than its length", file.path),
+            ));
+        }
 
-    let hash = murmur3(Readable::as_trait(target), body_pos, body_size, 0x31082007)?;
+        // The stored length is only known once the data is compressed.
+        let length = target.pos()? - data_pos;
+        target.write_u64le_at(entry_pos, length)?;
+        length
+    };
+    target.write_bytes(&vec![0; padding])?;
 
-    Ok((hash_pos, hash))
+    if checksum {
+        Ok(Some(crc32(
+            Readable::as_trait(target),
+            &data_pos,
+            &length,
+            &buffer_size,
+        )?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Hashes `length` bytes at `offset` to find entries with identical data.
+fn content_hash(
+    reader: &mut dyn Readable,
+    offset: u64,
+    length: u64,
+    buffer_size: u64,
+) -> Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    let mut hashed = 0;
+    while hashed < length {
+        let chunk = reader.read_bytes_at(offset + hashed, buffer_size.min(length - hashed))?;
+        hasher.update(&chunk);
+        hashed += chunk.len() as u64;
+    }
+    Ok(hasher.finalize().into())
+}
+
+/// Copies `length` bytes at `offset` from `reader` to the current position of
+/// `target`.
+///
+/// Unlike [`Readable::copy_at`], the reader and the target do not have to share
+/// a lifetime, so entries can be read from buffers that only live during a write.
+pub(crate) fn copy(
+    reader: &mut dyn Readable,
+    offset: u64,
+    length: u64,
+    target: &mut dyn Rw,
+    buffer_size: u64,
+) -> Result<()> {
+    let mut copied = 0;
+    while copied < length {
+        let chunk = reader.read_bytes_at(offset + copied, buffer_size.min(length - copied))?;
+        target.write_bytes(&chunk)?;
+        copied += chunk.len() as u64;
+    }
+    Ok(())
 }
 
 // TODO: Implement this inside the create function
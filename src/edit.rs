@@ -0,0 +1,243 @@
+use crate::{create, find_main_file, Entry, EntryWithSource, File, Link, Metadata};
+use dh::{recommended::*, Readable, Rw};
+use std::io::{Error, ErrorKind, Result};
+
+/// Writes a copy of an unencrypted archive without the given entries.
+///
+/// Removing a directory also removes everything inside it. The data of the
+/// remaining entries is copied from `source` as is, which has to be the
+/// archive `meta` was read from, or from [`Metadata::decompressed`] if the
+/// body is compressed. Like [`create`](crate::create), this returns
+/// the checksum to be written with [`write_hash`](crate::write_hash).
+pub fn remove<'a>(
+    source: &'a mut dyn Readable<'a>,
+    meta: &Metadata,
+    paths: &[&str],
+    target: &'a mut dyn Rw<'a>,
+    buffer_size: u64,
+) -> Result<(u64, u32)> {
+    check_unencrypted(meta)?;
+    for path in paths {
+        if !meta.files.iter().any(|file| file.path == *path) {
+            return Err(not_found(path));
+        }
+    }
+
+    let directories: Vec<&str> = paths
+        .iter()
+        .filter(|path| meta.files.iter().any(|f| f.directory && f.path == **path))
+        .copied()
+        .collect();
+    let removed = |file: &Entry| {
+        paths.contains(&file.path.as_str())
+            || directories.iter().any(|directory| {
+                file.path
+                    .strip_prefix(directory)
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+    };
+    let mut entries = Vec::new();
+    let mut main_file = None;
+    for (i, file) in meta.files.iter().enumerate() {
+        if removed(file) {
+            continue;
+        }
+        if meta.main_file == Some(i as u32) {
+            main_file = Some(entries.len() as u32);
+        }
+        entries.push((file, 0));
+    }
+
+    let mut decompressed = meta.decompressed.clone().map(dh::data::read);
+    let source: &mut dyn Readable = match &mut decompressed {
+        Some(body) => body,
+        None => source,
+    };
+
+    create::write(
+        meta.version,
+        &entries,
+        &mut [source],
+        true,
+        None,
+        main_file,
+        meta.extensions.as_ref(),
+        target,
+        buffer_size,
+    )
+}
+
+/// Writes a copy of an unencrypted archive in which the data of the file at
+/// `path` is read from `reader` instead.
+///
+/// The entry keeps its attributes. The data of a link is its target, so a
+/// link keeps its kind and points to what `reader` holds. All other entries
+/// are copied from `source` as is, see [`remove`].
+pub fn replace<'a>(
+    source: &'a mut dyn Readable<'a>,
+    meta: &Metadata,
+    path: &str,
+    reader: &'a mut dyn Readable<'a>,
+    target: &'a mut dyn Rw<'a>,
+    buffer_size: u64,
+) -> Result<(u64, u32)> {
+    let length = reader.size()?;
+    let replacement = Entry::from(File {
+        path: path.to_string(),
+        length,
+        ..Default::default()
+    });
+    replace_with(
+        source,
+        meta,
+        path,
+        &replacement,
+        reader,
+        target,
+        buffer_size,
+    )
+}
+
+/// Like [`replace`], but the entry at `path` becomes `replacement`, whose
+/// data is stored uncompressed.
+///
+/// The entry keeps its path. Its attributes and link are kept unless
+/// `replacement` has its own.
+pub fn replace_entry<'a>(
+    source: &'a mut dyn Readable<'a>,
+    meta: &Metadata,
+    path: &str,
+    replacement: EntryWithSource<'a>,
+    target: &'a mut dyn Rw<'a>,
+    buffer_size: u64,
+) -> Result<(u64, u32)> {
+    let EntryWithSource(replacement, reader) = replacement;
+    replace_with(source, meta, path, replacement, reader, target, buffer_size)
+}
+
+fn replace_with<'a>(
+    source: &'a mut dyn Readable<'a>,
+    meta: &Metadata,
+    path: &str,
+    replacement: &Entry,
+    reader: &'a mut dyn Readable<'a>,
+    target: &'a mut dyn Rw<'a>,
+    buffer_size: u64,
+) -> Result<(u64, u32)> {
+    check_unencrypted(meta)?;
+    let mut replacements = Vec::new();
+    for file in &meta.files {
+        if file.directory || file.path != path {
+            replacements.push(None);
+            continue;
+        }
+        let link = match (&replacement.link, &file.link) {
+            (Some(link), _) => Some(link.clone()),
+            (None, Some(link)) => {
+                let data = reader.read_bytes_at(replacement.offset, replacement.length)?;
+                let data = String::from_utf8(data).map_err(|_| {
+                    Error::new(ErrorKind::InvalidData, "Link target is not valid UTF-8")
+                })?;
+                Some(match link {
+                    Link::Symbolic(_) => Link::Symbolic(data),
+                    Link::Hard(_) => Link::Hard(data),
+                })
+            }
+            (None, None) => None,
+        };
+        replacements.push(Some(Entry {
+            attributes: replacement.attributes.or(file.attributes),
+            link,
+            ..Entry::from(File {
+                path: path.to_string(),
+                directory: false,
+                offset: replacement.offset,
+                length: replacement.length,
+            })
+        }));
+    }
+    if replacements.iter().all(Option::is_none) {
+        return Err(not_found(path));
+    }
+    let entries: Vec<_> = meta
+        .files
+        .iter()
+        .zip(&replacements)
+        .map(|(file, replacement)| match replacement {
+            Some(replacement) => (replacement, 1),
+            None => (file, 0),
+        })
+        .collect();
+
+    let mut decompressed = meta.decompressed.clone().map(dh::data::read);
+    let source: &mut dyn Readable = match &mut decompressed {
+        Some(body) => body,
+        None => source,
+    };
+
+    create::write(
+        meta.version,
+        &entries,
+        &mut [source, reader],
+        true,
+        None,
+        meta.main_file,
+        meta.extensions.as_ref(),
+        target,
+        buffer_size,
+    )
+}
+
+/// Writes a copy of an unencrypted archive whose main file is the regular
+/// file at `path`, or which has no main file if `path` is `None`.
+///
+/// All entries are copied from `source` as is, see [`remove`].
+pub fn set_main_file<'a>(
+    source: &'a mut dyn Readable<'a>,
+    meta: &Metadata,
+    path: Option<&str>,
+    target: &'a mut dyn Rw<'a>,
+    buffer_size: u64,
+) -> Result<(u64, u32)> {
+    check_unencrypted(meta)?;
+    let main_file = match path {
+        Some(path) => Some(find_main_file(&meta.files, path)?),
+        None => None,
+    };
+    let entries: Vec<_> = meta.files.iter().map(|file| (file, 0)).collect();
+
+    let mut decompressed = meta.decompressed.clone().map(dh::data::read);
+    let source: &mut dyn Readable = match &mut decompressed {
+        Some(body) => body,
+        None => source,
+    };
+
+    create::write(
+        meta.version,
+        &entries,
+        &mut [source],
+        true,
+        None,
+        main_file,
+        meta.extensions.as_ref(),
+        target,
+        buffer_size,
+    )
+}
+
+fn check_unencrypted(meta: &Metadata) -> Result<()> {
+    if meta.encryption.is_some() {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "Editing encrypted archives is not supported",
+        ));
+    }
+    Ok(())
+}
+
+fn not_found(path: &str) -> Error {
+    Error::new(
+        ErrorKind::NotFound,
+        format!("No entry with path {:?}", path),
+    )
+}
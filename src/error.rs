@@ -0,0 +1,56 @@
+use std::{fmt, io};
+
+/// Describes why an archive could not be read.
+///
+/// These are returned wrapped in an [`io::Error`], so they can be recovered with
+/// [`io::Error::get_ref`] and [`downcast_ref`](std::error::Error::downcast_ref).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveError {
+    /// The ciphertext is empty or not a multiple of the AES block size.
+    TruncatedCiphertext,
+    /// The decrypted body does not end with valid PKCS#7 padding.
+    BadPadding,
+    /// The password hash matches, but the decrypted body cannot be parsed.
+    Undecryptable,
+    /// The authentication tag does not match the header and ciphertext.
+    AuthenticationFailed,
+    /// The archive has no MAC, but [`Limits::require_authentication`](crate::Limits::require_authentication) is set.
+    Unauthenticated,
+    /// An entry or the extension section extends past the available bytes.
+    OutOfBounds,
+    /// The archive has more entries than [`Limits::max_entries`](crate::Limits::max_entries).
+    TooManyEntries,
+    /// A path is longer than [`Limits::max_path_length`](crate::Limits::max_path_length).
+    PathTooLong,
+    /// The entries are larger than [`Limits::max_total_size`](crate::Limits::max_total_size) in total,
+    /// or the decompressed body is larger than [`Limits::max_body_length`](crate::Limits::max_body_length).
+    TooLarge,
+    /// The key derivation function is more expensive than [`Limits`](crate::Limits) allow.
+    KdfTooExpensive,
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ArchiveError::*;
+        f.write_str(match self {
+            TruncatedCiphertext => "Ciphertext is truncated",
+            BadPadding => "Decrypted body has invalid padding",
+            Undecryptable => "Password is correct but the body cannot be decrypted",
+            AuthenticationFailed => "Archive authentication failed",
+            Unauthenticated => "Archive is not authenticated",
+            OutOfBounds => "Entry extends past the end of the archive",
+            TooManyEntries => "Archive has too many entries",
+            PathTooLong => "Entry path is too long",
+            TooLarge => "Archive content is too large",
+            KdfTooExpensive => "Key derivation is too expensive",
+        })
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl From<ArchiveError> for io::Error {
+    fn from(error: ArchiveError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
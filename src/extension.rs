@@ -0,0 +1,464 @@
+use crate::{compression, ArchiveError, Attributes, Compression, Extensions, Kdf, Limits};
+use dh::{recommended::*, Readable, Rw, Writable};
+use std::io::{Error, ErrorKind, Result};
+
+// The extension section lives after the body. It is announced in the last
+// 32 bytes of the v3 reserved header space, which readers that only check the
+// first 32 reserved bytes for the v3 marker leave alone.
+pub const MARKER_POS: u64 = 96;
+const MARKER: &[u8; 4] = b"HSSX";
+
+// Records whose tag has this bit set change how the archive has to be read,
+// so a reader that does not know them must refuse the archive.
+const CRITICAL: u16 = 0x8000;
+
+const TAG_KDF: u16 = CRITICAL | 1;
+const TAG_MAC: u16 = CRITICAL | 2;
+const TAG_CHECKSUMS: u16 = 3;
+const TAG_COMPRESSION: u16 = CRITICAL | 4;
+const TAG_BODY_COMPRESSION: u16 = CRITICAL | 5;
+const TAG_DUPLICATES: u16 = CRITICAL | 6;
+const TAG_ATTRIBUTES: u16 = 7;
+// Readers that do not know links still read their targets as file contents.
+const TAG_LINKS: u16 = 8;
+const TAG_COMMENT: u16 = 9;
+const TAG_PROPERTIES: u16 = 10;
+// The checksums of encrypted archives follow the entries in the encrypted
+// body, where readers that do not know them never look.
+const TAG_BODY_CHECKSUMS: u16 = 11;
+
+const LINK_SYMBOLIC: u8 = 0;
+const LINK_HARD: u8 = 1;
+
+/// The decoded extension section, including the parts that only matter to
+/// the reader.
+#[derive(Default)]
+pub struct Section {
+    pub extensions: Extensions,
+    /// The MAC and its position, which is the last 32 bytes of the section.
+    pub mac: Option<([u8; 32], u64)>,
+    pub checksums: Option<Vec<u32>>,
+    /// Whether the entry checksums are stored at the end of the body instead
+    /// of in [`checksums`](Self::checksums), as for encrypted archives.
+    pub body_checksums: bool,
+    /// The compression and uncompressed length of every entry.
+    pub compression: Option<Vec<(Compression, u64)>>,
+    /// The length of the body after decompressing it, if
+    /// [`Extensions::body_compression`] is set.
+    pub body_length: u64,
+    /// Pairs of an entry without data and the earlier entry holding its data.
+    pub duplicates: Option<Vec<(u32, u32)>>,
+    /// The attributes of every entry.
+    pub attributes: Option<Vec<Option<Attributes>>>,
+    /// Pairs of a link entry and whether it is a hard link.
+    pub links: Option<Vec<(u32, bool)>>,
+    /// The tags and payloads of non-critical records this reader does not
+    /// know, so rewriting the section keeps them.
+    pub unknown: Vec<(u16, Vec<u8>)>,
+}
+
+// Archives with critical records would be misread by readers that predate
+// the extension section, which would e.g. return compressed data as is. The
+// first half of their reserved header space holds this entry header instead
+// of zeros, whose path is not valid UTF-8. Such readers take the archive for
+// version 2 and fail on the path, while no version 2 archive can start so.
+const GUARD_POS: u64 = 64;
+const GUARD: [u8; 32] = {
+    let mut guard = [0; 32];
+    guard[8] = 22;
+    guard[10] = 0xff;
+    guard
+};
+
+pub fn is_guard(reserved: &[u8]) -> bool {
+    reserved == GUARD
+}
+
+/// Makes readers that do not know the extension section refuse the archive
+/// if `section` contains critical records.
+pub fn write_guard(target: &mut dyn Writable, section: &[u8]) -> Result<()> {
+    let mut pos = 0;
+    while pos + 6 <= section.len() {
+        let tag = u16::from_le_bytes([section[pos], section[pos + 1]]);
+        if tag & CRITICAL != 0 {
+            return target.write_bytes_at(GUARD_POS, &GUARD);
+        }
+        pos += 6 + u32::from_le_bytes(section[pos + 2..pos + 6].try_into().unwrap()) as usize;
+    }
+    Ok(())
+}
+
+pub fn read_marker(reader: &mut dyn Readable) -> Result<Option<(u64, u32)>> {
+    if reader.read_bytes_at(MARKER_POS, 4)? != MARKER {
+        return Ok(None);
+    }
+    let offset = reader.read_u64le_at(MARKER_POS + 4)?;
+    let length = reader.read_u32le_at(MARKER_POS + 12)?;
+    if offset < 128 || offset.saturating_add(length as u64) > reader.size()? {
+        return Err(ArchiveError::OutOfBounds.into());
+    }
+    Ok(Some((offset, length)))
+}
+
+pub fn write_marker(target: &mut dyn Writable, offset: u64, length: u32) -> Result<()> {
+    target.write_bytes_at(MARKER_POS, MARKER)?;
+    target.write_u64le_at(MARKER_POS + 4, offset)?;
+    target.write_u32le_at(MARKER_POS + 12, length)
+}
+
+pub fn read(
+    reader: &mut dyn Readable,
+    offset: u64,
+    length: u32,
+    limits: &Limits,
+) -> Result<Section> {
+    let mut extensions = Extensions::default();
+    let mut mac = None;
+    let mut checksums = None;
+    let mut body_checksums = false;
+    let mut compression = None;
+    let mut body_length = 0;
+    let mut duplicates = None;
+    let mut attributes = None;
+    let mut links = None;
+    let mut unknown = Vec::new();
+    let mut section = dh::data::read(reader.read_bytes_at(offset, length as u64)?);
+    let end = length as u64;
+
+    while section.pos()? < end {
+        let tag = section.read_u16le()?;
+        let size = section.read_u32le()? as u64;
+        if section.pos()? + size > end {
+            return Err(ArchiveError::OutOfBounds.into());
+        }
+        let mut record = dh::data::read(section.read_bytes(size)?);
+
+        match tag {
+            TAG_KDF => extensions.kdf = read_kdf(&mut record, limits)?,
+            TAG_MAC => {
+                // The MAC covers everything before it, so nothing may follow.
+                if size != 32 || section.pos()? != end {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "MAC must be the last extension record",
+                    ));
+                }
+                extensions.authenticated = true;
+                mac = Some((
+                    record.read_bytes(32)?.try_into().unwrap(),
+                    offset + end - 32,
+                ));
+            }
+            TAG_CHECKSUMS => {
+                extensions.entry_checksums = true;
+                checksums = Some(
+                    (0..size / 4)
+                        .map(|_| record.read_u32le())
+                        .collect::<Result<_>>()?,
+                );
+            }
+            TAG_BODY_CHECKSUMS => {
+                extensions.entry_checksums = true;
+                body_checksums = true;
+            }
+            TAG_COMPRESSION => {
+                compression = Some(
+                    (0..size / 9)
+                        .map(|_| {
+                            Ok((
+                                compression::from_id(record.read_u8()?)?,
+                                record.read_u64le()?,
+                            ))
+                        })
+                        .collect::<Result<_>>()?,
+                );
+            }
+            TAG_BODY_COMPRESSION => {
+                extensions.body_compression = compression::from_id(record.read_u8()?)?;
+                body_length = record.read_u64le()?;
+            }
+            TAG_DUPLICATES => {
+                extensions.deduplicate = true;
+                duplicates = Some(
+                    (0..size / 8)
+                        .map(|_| Ok((record.read_u32le()?, record.read_u32le()?)))
+                        .collect::<Result<_>>()?,
+                );
+            }
+            TAG_ATTRIBUTES => {
+                attributes = Some(
+                    (0..size / 21)
+                        .map(|_| read_attributes(&mut record))
+                        .collect::<Result<_>>()?,
+                );
+            }
+            TAG_LINKS => {
+                links = Some(
+                    (0..size / 5)
+                        .map(|_| {
+                            let entry = record.read_u32le()?;
+                            match record.read_u8()? {
+                                LINK_SYMBOLIC => Ok((entry, false)),
+                                LINK_HARD => Ok((entry, true)),
+                                kind => Err(Error::new(
+                                    ErrorKind::InvalidData,
+                                    format!("Unknown link type {}", kind),
+                                )),
+                            }
+                        })
+                        .collect::<Result<_>>()?,
+                );
+            }
+            TAG_COMMENT => extensions.comment = Some(read_string(&mut record, size)?),
+            TAG_PROPERTIES => {
+                while record.pos()? < size {
+                    let key_length = record.read_u16le()? as u64;
+                    let key = read_string(&mut record, key_length)?;
+                    let value_length = record.read_u32le()? as u64;
+                    let value = read_string(&mut record, value_length)?;
+                    extensions.properties.insert(key, value);
+                }
+            }
+            _ if tag & CRITICAL != 0 => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Unsupported extension {:#06x}", tag),
+                ))
+            }
+            _ => unknown.push((tag, record.read_bytes(size)?)),
+        }
+    }
+
+    Ok(Section {
+        extensions,
+        mac,
+        checksums,
+        body_checksums,
+        compression,
+        body_length,
+        duplicates,
+        attributes,
+        links,
+        unknown,
+    })
+}
+
+/// Writes the section of an unencrypted archive at the current position,
+/// which has to be the end of its body, and announces it in the header.
+pub fn write(target: &mut dyn Rw, section: &Section) -> Result<()> {
+    let offset = target.pos()?;
+    let section = encode(section, false)?;
+    write_marker(Writable::as_trait(target), offset, section.len() as u32)?;
+    write_guard(Writable::as_trait(target), &section)?;
+    target.write_bytes(&section)
+}
+
+/// Serializes the extension section.
+///
+/// If the archive is authenticated, the MAC record comes last and is left
+/// zeroed, so the caller can fill in the last 32 bytes once the header is final.
+pub fn encode(section: &Section, encrypted: bool) -> Result<Vec<u8>> {
+    let extensions = &section.extensions;
+    let mut encoded = dh::data::rw_empty();
+
+    if encrypted && extensions.kdf != Kdf::Sha256 {
+        let mut record = dh::data::rw_empty();
+        write_kdf(&mut record, &extensions.kdf)?;
+        write_record(&mut encoded, TAG_KDF, dh::data::close(record))?;
+    }
+
+    if let Some(checksums) = &section.checksums {
+        let mut record = dh::data::rw_empty();
+        for checksum in checksums {
+            record.write_u32le(*checksum)?;
+        }
+        write_record(&mut encoded, TAG_CHECKSUMS, dh::data::close(record))?;
+    }
+
+    if section.body_checksums {
+        write_record(&mut encoded, TAG_BODY_CHECKSUMS, vec![])?;
+    }
+
+    if let Some(entries) = &section.compression {
+        let mut record = dh::data::rw_empty();
+        for (compression, uncompressed_length) in entries {
+            record.write_u8(compression::id(*compression))?;
+            record.write_u64le(*uncompressed_length)?;
+        }
+        write_record(&mut encoded, TAG_COMPRESSION, dh::data::close(record))?;
+    }
+
+    if extensions.body_compression != Compression::None {
+        let mut record = dh::data::rw_empty();
+        record.write_u8(compression::id(extensions.body_compression))?;
+        record.write_u64le(section.body_length)?;
+        write_record(&mut encoded, TAG_BODY_COMPRESSION, dh::data::close(record))?;
+    }
+
+    if let Some(duplicates) = &section.duplicates {
+        let mut record = dh::data::rw_empty();
+        for (entry, original) in duplicates {
+            record.write_u32le(*entry)?;
+            record.write_u32le(*original)?;
+        }
+        write_record(&mut encoded, TAG_DUPLICATES, dh::data::close(record))?;
+    }
+
+    if let Some(entries) = &section.attributes {
+        let mut record = dh::data::rw_empty();
+        for attributes in entries {
+            write_attributes(&mut record, attributes.as_ref())?;
+        }
+        write_record(&mut encoded, TAG_ATTRIBUTES, dh::data::close(record))?;
+    }
+
+    if let Some(links) = &section.links {
+        let mut record = dh::data::rw_empty();
+        for (entry, hard) in links {
+            record.write_u32le(*entry)?;
+            record.write_u8(if *hard { LINK_HARD } else { LINK_SYMBOLIC })?;
+        }
+        write_record(&mut encoded, TAG_LINKS, dh::data::close(record))?;
+    }
+
+    if let Some(comment) = &extensions.comment {
+        write_record(&mut encoded, TAG_COMMENT, comment.as_bytes().to_vec())?;
+    }
+
+    if !extensions.properties.is_empty() {
+        let mut record = dh::data::rw_empty();
+        for (key, value) in &extensions.properties {
+            if key.len() > u16::MAX as usize {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Property key {:?} is too long", key),
+                ));
+            }
+            record.write_u16le(key.len() as u16)?;
+            record.write_utf8(key)?;
+            record.write_u32le(value.len() as u32)?;
+            record.write_utf8(value)?;
+        }
+        write_record(&mut encoded, TAG_PROPERTIES, dh::data::close(record))?;
+    }
+
+    for (tag, payload) in &section.unknown {
+        write_record(&mut encoded, *tag, payload.clone())?;
+    }
+
+    if encrypted && extensions.authenticated {
+        write_record(&mut encoded, TAG_MAC, vec![0; 32])?;
+    }
+
+    let encoded = dh::data::close(encoded);
+    // The marker stores the length as a u32, which also bounds every record.
+    if encoded.len() > u32::MAX as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Extension section is too large",
+        ));
+    }
+    Ok(encoded)
+}
+
+fn write_record(section: &mut dyn Writable, tag: u16, payload: Vec<u8>) -> Result<()> {
+    section.write_u16le(tag)?;
+    section.write_u32le(payload.len() as u32)?;
+    section.write_bytes(&payload)
+}
+
+fn read_string(record: &mut dyn Readable, length: u64) -> Result<String> {
+    String::from_utf8(record.read_bytes(length)?)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "Extension text is not valid UTF-8"))
+}
+
+fn read_kdf(record: &mut dyn Readable, limits: &Limits) -> Result<Kdf> {
+    let kdf = match record.read_u8()? {
+        0 => Kdf::Sha256,
+        1 => {
+            let iterations = record.read_u32le()?;
+            let salt = record.read_bytes(16)?.try_into().unwrap();
+            if iterations == 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "PBKDF2 iteration count must not be zero",
+                ));
+            }
+            Kdf::Pbkdf2 { iterations, salt }
+        }
+        2 => Kdf::Argon2 {
+            memory: record.read_u32le()?,
+            iterations: record.read_u32le()?,
+            parallelism: record.read_u32le()?,
+            salt: record.read_bytes(16)?.try_into().unwrap(),
+        },
+        id => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Unsupported key derivation function {}", id),
+            ))
+        }
+    };
+
+    let too_expensive = match kdf {
+        Kdf::Sha256 => false,
+        Kdf::Pbkdf2 { iterations, .. } => iterations > limits.max_pbkdf2_iterations,
+        Kdf::Argon2 {
+            memory,
+            iterations,
+            parallelism,
+            ..
+        } => {
+            memory > limits.max_argon2_memory
+                || iterations > limits.max_argon2_iterations
+                || parallelism > limits.max_argon2_parallelism
+        }
+    };
+    if too_expensive {
+        return Err(ArchiveError::KdfTooExpensive.into());
+    }
+    Ok(kdf)
+}
+
+fn write_kdf(record: &mut dyn Writable, kdf: &Kdf) -> Result<()> {
+    match kdf {
+        Kdf::Sha256 => record.write_u8(0),
+        Kdf::Pbkdf2 { iterations, salt } => {
+            record.write_u8(1)?;
+            record.write_u32le(*iterations)?;
+            record.write_bytes(salt)
+        }
+        Kdf::Argon2 {
+            memory,
+            iterations,
+            parallelism,
+            salt,
+        } => {
+            record.write_u8(2)?;
+            record.write_u32le(*memory)?;
+            record.write_u32le(*iterations)?;
+            record.write_u32le(*parallelism)?;
+            record.write_bytes(salt)
+        }
+    }
+}
+
+fn read_attributes(record: &mut dyn Readable) -> Result<Option<Attributes>> {
+    let present = record.read_u8()? != 0;
+    let attributes = Attributes {
+        mtime: record.read_i64le()?,
+        mode: record.read_u32le()?,
+        uid: record.read_u32le()?,
+        gid: record.read_u32le()?,
+    };
+    Ok(present.then_some(attributes))
+}
+
+fn write_attributes(record: &mut dyn Writable, attributes: Option<&Attributes>) -> Result<()> {
+    record.write_u8(attributes.is_some() as u8)?;
+    let attributes = attributes.copied().unwrap_or_default();
+    record.write_i64le(attributes.mtime)?;
+    record.write_u32le(attributes.mode)?;
+    record.write_u32le(attributes.uid)?;
+    record.write_u32le(attributes.gid)
+}
@@ -1,14 +1,29 @@
-use crate::File;
+use crate::{compression, Compression, Entry};
 use dh::{recommended::*, Readable, Writable};
-use std::io::Result;
+use std::io::{copy, Result};
 
+/// Writes the data of an entry to `target` at `target_pos`.
+///
+/// Compressed entries are decompressed, so [`Entry::uncompressed_length`] bytes
+/// are written.
 pub fn extract<'a>(
     source: &'a mut dyn Readable<'a>,
-    file: &File,
+    file: &Entry,
     target: &'a mut dyn Writable<'a>,
     buffer_size: u64,
     target_pos: u64,
 ) -> Result<()> {
-    source.copy_to_at(file.offset, target_pos, file.length, target, buffer_size)?;
+    if file.compression == Compression::None {
+        source.copy_to_at(file.offset, target_pos, file.length, target, buffer_size)?;
+        return Ok(());
+    }
+
+    let source_pos = source.pos()?;
+    let target_pos_before = target.pos()?;
+    target.to(target_pos)?;
+    let result = copy(&mut compression::entry_reader(source, file)?, target);
+    target.to(target_pos_before)?;
+    source.to(source_pos)?;
+    result?;
     Ok(())
 }
@@ -0,0 +1,651 @@
+use crate::{
+    create, entry_reader, AttributePolicy, Attributes, Entry, Extensions, File, Link, LinkPolicy,
+    Metadata,
+};
+use dh::{data::RData, file::RFile, Readable, Rw};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    io::{copy, Error, ErrorKind, Result},
+    path::{Component, Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Writes an archive containing everything inside `directory`.
+///
+/// Entries are named relative to `directory` and separated by `/`. For
+/// version 3, the attributes of every entry are captured as well. Files are
+/// opened one at a time while their entry is written. Like
+/// [`create`](crate::create), this returns the checksum to be written with
+/// [`write_hash`](crate::write_hash).
+pub fn pack(
+    version: u8,
+    directory: &Path,
+    encryption: Option<(&str, &[u8; 16])>,
+    extensions: Option<&Extensions>,
+    target: &mut dyn Rw,
+    buffer_size: u64,
+) -> Result<(u64, u32)> {
+    let mut files = Vec::new();
+    let mut paths = Vec::new();
+    walk(
+        directory,
+        "",
+        version,
+        &mut files,
+        &mut paths,
+        &mut HashMap::new(),
+    )?;
+
+    // Directories and links share the first, empty reader.
+    let mut entries = Vec::new();
+    let mut sources = Files {
+        empty: dh::data::read(vec![]),
+        paths: Vec::new(),
+        open: None,
+    };
+    for (file, path) in files.iter().zip(&paths) {
+        match path {
+            Some(path) => {
+                sources.paths.push(path);
+                entries.push((file, sources.paths.len()));
+            }
+            None => entries.push((file, 0)),
+        }
+    }
+
+    create::write_sources(
+        version,
+        &entries,
+        &mut sources,
+        false,
+        encryption,
+        None,
+        extensions,
+        target,
+        buffer_size,
+    )
+}
+
+/// The readers of a packed directory. Every entry is read to the end before
+/// the next one is written, so only the file of the current entry is kept open.
+struct Files<'p> {
+    empty: RData,
+    paths: Vec<&'p Path>,
+    open: Option<(usize, RFile)>,
+}
+
+impl<'a> create::Sources<'a> for Files<'_> {
+    fn reader(&mut self, source: usize) -> Result<&mut dyn Readable<'a>> {
+        if source == 0 {
+            return Ok(&mut self.empty);
+        }
+        if self.open.as_ref().is_none_or(|(open, _)| *open != source) {
+            // The previous file is closed before the next one is opened.
+            self.open = None;
+            self.open = Some((source, dh::file::open_r(self.paths[source - 1])?));
+        }
+        Ok(&mut self.open.as_mut().unwrap().1)
+    }
+}
+
+/// Collects the entries inside `directory`, sorted by name so archives are
+/// reproducible.
+///
+/// Symbolic links are stored as links. For version 3, files with several
+/// hard links are stored once, later paths become hard links to the first.
+fn walk(
+    directory: &Path,
+    prefix: &str,
+    version: u8,
+    files: &mut Vec<Entry>,
+    paths: &mut Vec<Option<PathBuf>>,
+    inodes: &mut HashMap<(u64, u64), String>,
+) -> Result<()> {
+    let mut children = fs::read_dir(directory)?.collect::<Result<Vec<_>>>()?;
+    children.sort_by_key(|child| child.file_name());
+
+    for child in children {
+        let path = format!("{}{}", prefix, utf8(child.file_name())?);
+        let metadata = fs::symlink_metadata(child.path())?;
+        let attributes = (version >= 3).then(|| capture(&metadata)).transpose()?;
+
+        if metadata.is_dir() {
+            files.push(Entry {
+                file: File {
+                    path: path.clone(),
+                    directory: true,
+                    ..Default::default()
+                },
+                attributes,
+                ..Default::default()
+            });
+            paths.push(None);
+            walk(
+                &child.path(),
+                &format!("{}/", path),
+                version,
+                files,
+                paths,
+                inodes,
+            )?;
+        } else if metadata.is_symlink() {
+            let target = utf8(fs::read_link(child.path())?.into_os_string())?;
+            #[cfg(windows)]
+            let target = target.replace('\\', "/");
+            files.push(Entry {
+                file: File {
+                    path,
+                    ..Default::default()
+                },
+                link: Some(Link::Symbolic(target)),
+                ..Default::default()
+            });
+            paths.push(None);
+        } else if metadata.is_file() {
+            if let Some(inode) = inode(&metadata).filter(|_| version >= 3) {
+                if let Some(first) = inodes.get(&inode) {
+                    files.push(Entry {
+                        file: File {
+                            path,
+                            ..Default::default()
+                        },
+                        link: Some(Link::Hard(first.clone())),
+                        ..Default::default()
+                    });
+                    paths.push(None);
+                    continue;
+                }
+                inodes.insert(inode, path.clone());
+            }
+            files.push(Entry {
+                attributes,
+                ..Entry::from(File {
+                    path,
+                    length: metadata.len(),
+                    ..Default::default()
+                })
+            });
+            paths.push(Some(child.path()));
+        } else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "{:?} is neither a file, a directory nor a link",
+                    child.path()
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn utf8(name: std::ffi::OsString) -> Result<String> {
+    name.into_string().map_err(|name| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{:?} is not valid UTF-8", name),
+        )
+    })
+}
+
+/// Identifies files with several hard links.
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Writes every entry of an archive into `directory`.
+///
+/// `source` has to be the archive `meta` was read from. Encrypted archives
+/// are read from [`Encryption::decrypted`](crate::Encryption::decrypted), so
+/// `meta` has to be read with the right password. Entry paths that would end
+/// up outside of `directory` and links that would point there are rejected
+/// before anything is written. Links are handled after all other entries, so
+/// nothing is written through them. Attributes are restored last, except for
+/// links, as far as `attributes` allows.
+pub fn unpack(
+    source: &mut dyn Readable,
+    meta: &Metadata,
+    directory: &Path,
+    links: LinkPolicy,
+    attributes: AttributePolicy,
+    buffer_size: u64,
+) -> Result<()> {
+    if meta
+        .encryption
+        .as_ref()
+        .is_some_and(|e| e.hash != e.hash_expected)
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Archive has not been decrypted",
+        ));
+    }
+    let paths = meta
+        .files
+        .iter()
+        .map(|file| entry_path(directory, &file.path))
+        .collect::<Result<Vec<_>>>()?;
+
+    let tree = Tree::new(&meta.files);
+    let mut targets = Vec::new();
+    for (i, file) in meta.files.iter().enumerate() {
+        let Some(link) = &file.link else {
+            continue;
+        };
+        if links == LinkPolicy::Reject {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Entry {:?} is a link", file.path),
+            ));
+        }
+        targets.push((i, tree.target(i, link, links == LinkPolicy::Copy)?));
+    }
+
+    match meta.body() {
+        Some(body) => write_entries(
+            &mut dh::data::read_ref(body),
+            &tree,
+            &paths,
+            &targets,
+            links,
+            buffer_size,
+        )?,
+        None => write_entries(source, &tree, &paths, &targets, links, buffer_size)?,
+    }
+
+    // Directories come last, as writing their contents changes their
+    // modification time and read-only ones could not be written to.
+    for (file, path) in meta.files.iter().zip(&paths).rev() {
+        if let (Some(stored), None) = (&file.attributes, &file.link) {
+            if !file.directory {
+                restore(path, stored, attributes)?;
+            }
+        }
+    }
+    for (file, path) in meta.files.iter().zip(&paths).rev() {
+        if let Some(stored) = &file.attributes {
+            if file.directory {
+                restore(path, stored, attributes)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_entries(
+    source: &mut dyn Readable,
+    tree: &Tree,
+    paths: &[PathBuf],
+    targets: &[(usize, usize)],
+    links: LinkPolicy,
+    buffer_size: u64,
+) -> Result<()> {
+    for (file, path) in tree.files.iter().zip(paths) {
+        if file.directory {
+            fs::create_dir_all(path)?;
+        } else if file.link.is_none() {
+            write_file(source, file, path, buffer_size)?;
+        }
+    }
+
+    for &(i, target) in targets {
+        let path = &paths[i];
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match (&tree.files[i].link, links) {
+            (Some(Link::Symbolic(link_target)), LinkPolicy::Create) => {
+                symlink(link_target, path, tree.files[target].directory)?
+            }
+            (Some(Link::Hard(_)), LinkPolicy::Create) => fs::hard_link(&paths[target], path)?,
+            _ => tree.copy(source, target, path, &mut HashSet::new(), buffer_size)?,
+        }
+    }
+    Ok(())
+}
+
+fn write_file(
+    source: &mut dyn Readable,
+    file: &Entry,
+    path: &Path,
+    buffer_size: u64,
+) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut target = std::io::BufWriter::with_capacity(
+        buffer_size.clamp(1, usize::MAX as u64) as usize,
+        fs::File::create(path)?,
+    );
+    copy(&mut entry_reader(source, file)?, &mut target)?;
+    target.into_inner().map_err(|e| e.into_error())?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, path: &Path, _directory: bool) -> Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn symlink(target: &str, path: &Path, directory: bool) -> Result<()> {
+    let target = target.replace('/', "\\");
+    if directory {
+        std::os::windows::fs::symlink_dir(target, path)
+    } else {
+        std::os::windows::fs::symlink_file(target, path)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &str, _path: &Path, _directory: bool) -> Result<()> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "Symbolic links are not supported on this platform",
+    ))
+}
+
+// Links are followed at most this often while resolving a single path,
+// like Linux does.
+const MAX_LINKS: u32 = 40;
+
+/// The entries of an archive, looked up by path to resolve links.
+struct Tree<'m> {
+    files: &'m [Entry],
+    indices: HashMap<&'m str, usize>,
+}
+
+impl<'m> Tree<'m> {
+    fn new(files: &'m [Entry]) -> Self {
+        Self {
+            files,
+            indices: files
+                .iter()
+                .enumerate()
+                .map(|(i, file)| (file.path.as_str(), i))
+                .collect(),
+        }
+    }
+
+    /// Returns the index of the entry a link points to.
+    ///
+    /// Without `follow`, symbolic links only have to stay inside the archive,
+    /// their target does not have to exist. Otherwise, links are followed
+    /// until an entry that is not a link is found.
+    fn target(&self, i: usize, link: &Link, follow: bool) -> Result<usize> {
+        let mut hops = 0;
+        let mut i = i;
+        let mut link = link;
+        loop {
+            let path = match link {
+                Link::Symbolic(target) => self.resolve(&self.files[i].path, target, &mut hops)?,
+                Link::Hard(target) => target.clone(),
+            };
+            let Some(&target) = self.indices.get(path.as_str()) else {
+                if !follow && matches!(link, Link::Symbolic(_)) {
+                    // Dangling, but inside the archive.
+                    return Ok(i);
+                }
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("Link {:?} points to a missing entry", self.files[i].path),
+                ));
+            };
+            match (&self.files[target].link, link) {
+                (None, Link::Hard(_)) if self.files[target].directory => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Hard link {:?} points to a directory", self.files[i].path),
+                    ))
+                }
+                (Some(_), Link::Hard(_)) if !follow => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Hard link {:?} points to a link", self.files[i].path),
+                    ))
+                }
+                (Some(next), _) if follow => {
+                    hops += 1;
+                    if hops > MAX_LINKS {
+                        return Err(too_many_links(&self.files[i].path));
+                    }
+                    i = target;
+                    link = next;
+                }
+                _ => return Ok(target),
+            }
+        }
+    }
+
+    /// Resolves the target of the symbolic link at `path` to an entry path,
+    /// following symbolic links on the way like the filesystem would.
+    ///
+    /// Fails if the target is absolute or leaves the root of the archive.
+    fn resolve(&self, path: &str, target: &str, hops: &mut u32) -> Result<String> {
+        let outside = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Link {:?} points outside of the archive", path),
+            )
+        };
+
+        let mut resolved: Vec<&str> = path.split('/').collect();
+        resolved.pop();
+        let mut pending: VecDeque<&str> = VecDeque::new();
+        if target.starts_with('/') {
+            return Err(outside());
+        }
+        pending.extend(target.split('/'));
+
+        while let Some(name) = pending.pop_front() {
+            match name {
+                "" | "." => {}
+                ".." => {
+                    resolved.pop().ok_or_else(outside)?;
+                }
+                name if is_plain(name) => {
+                    resolved.push(name);
+                    let current = resolved.join("/");
+                    if let Some(Link::Symbolic(next)) = self
+                        .indices
+                        .get(current.as_str())
+                        .and_then(|&i| self.files[i].link.as_ref())
+                    {
+                        *hops += 1;
+                        if *hops > MAX_LINKS {
+                            return Err(too_many_links(path));
+                        }
+                        if next.starts_with('/') {
+                            return Err(outside());
+                        }
+                        resolved.pop();
+                        for name in next.split('/').rev() {
+                            pending.push_front(name);
+                        }
+                    }
+                }
+                _ => return Err(outside()),
+            }
+        }
+
+        Ok(resolved.join("/"))
+    }
+
+    /// Writes a copy of the entry at index `i` to `path`, following links and
+    /// copying directories with everything inside them.
+    ///
+    /// Every directory is copied at most once into a single link, as links to
+    /// directories containing further links would otherwise multiply the
+    /// output with every level.
+    fn copy(
+        &self,
+        source: &mut dyn Readable,
+        i: usize,
+        path: &Path,
+        expanded: &mut HashSet<usize>,
+        buffer_size: u64,
+    ) -> Result<()> {
+        let file = &self.files[i];
+        if let Some(link) = &file.link {
+            let target = self.target(i, link, true)?;
+            return self.copy(source, target, path, expanded, buffer_size);
+        }
+        if !file.directory {
+            return write_file(source, file, path, buffer_size);
+        }
+        if !expanded.insert(i) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Directory {:?} is copied more than once", file.path),
+            ));
+        }
+
+        fs::create_dir_all(path)?;
+        let prefix = format!("{}/", file.path);
+        for (child, entry) in self.files.iter().enumerate() {
+            if let Some(name) = entry.path.strip_prefix(&prefix) {
+                if !name.contains('/') {
+                    self.copy(source, child, &path.join(name), expanded, buffer_size)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn too_many_links(path: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Too many levels of links in {:?}", path),
+    )
+}
+
+/// Resolves an entry path inside `directory`, rejecting absolute paths and
+/// anything that is not a plain file name, such as `..`.
+pub(crate) fn entry_path(directory: &Path, path: &str) -> Result<PathBuf> {
+    let mut resolved = directory.to_path_buf();
+    for name in path.split('/') {
+        if !is_plain(name) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Entry path {:?} is not safe to extract", path),
+            ));
+        }
+        resolved.push(name);
+    }
+    Ok(resolved)
+}
+
+/// Whether `name` is a single file name, which excludes `.`, `..` and on
+/// Windows also drive prefixes and backslashes.
+fn is_plain(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(component)), None) if component == name
+    )
+}
+
+fn capture(metadata: &fs::Metadata) -> Result<Attributes> {
+    let mtime = match metadata.modified()?.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(Attributes {
+            mtime,
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        let mode = match (metadata.is_dir(), metadata.permissions().readonly()) {
+            (true, false) => 0o755,
+            (true, true) => 0o555,
+            (false, false) => 0o644,
+            (false, true) => 0o444,
+        };
+        Ok(Attributes {
+            mtime,
+            mode,
+            ..Default::default()
+        })
+    }
+}
+
+fn restore(path: &Path, attributes: &Attributes, policy: AttributePolicy) -> Result<()> {
+    // Changing the owner clears the setuid and setgid bits, so it comes first.
+    #[cfg(unix)]
+    if policy == AttributePolicy::All {
+        use std::os::unix::fs::{chown, MetadataExt};
+        let metadata = fs::metadata(path)?;
+        if metadata.uid() != attributes.uid || metadata.gid() != attributes.gid {
+            match chown(path, Some(attributes.uid), Some(attributes.gid)) {
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => {}
+                result => result?,
+            }
+        }
+    }
+
+    let mtime = if attributes.mtime >= 0 {
+        UNIX_EPOCH + Duration::from_secs(attributes.mtime as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(attributes.mtime.unsigned_abs())
+    };
+    open_attributes(path)?.set_modified(mtime)?;
+    if policy == AttributePolicy::Timestamps {
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    let permissions = {
+        use std::os::unix::fs::PermissionsExt;
+        let mask = if policy == AttributePolicy::All {
+            0o7777
+        } else {
+            0o1777
+        };
+        fs::Permissions::from_mode(attributes.mode & mask)
+    };
+    #[cfg(not(unix))]
+    let permissions = {
+        let mut permissions = fs::metadata(path)?.permissions();
+        permissions.set_readonly(attributes.mode & 0o222 == 0);
+        permissions
+    };
+    fs::set_permissions(path, permissions)
+}
+
+/// Opens a file or directory to change its timestamps.
+#[cfg(windows)]
+fn open_attributes(path: &Path) -> Result<fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    // Writing attributes is also allowed for read-only files, and directories
+    // can only be opened with backup semantics.
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    fs::OpenOptions::new()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)
+}
+
+#[cfg(not(windows))]
+fn open_attributes(path: &Path) -> Result<fs::File> {
+    fs::File::open(path)
+}
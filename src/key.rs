@@ -0,0 +1,38 @@
+use crate::Kdf;
+use acr::hash::sha256;
+use argon2::{Algorithm, Argon2, Params, Version};
+use sha2::Sha256;
+use std::io::{Error, ErrorKind, Result};
+
+pub fn derive(password: &str, kdf: &Kdf) -> Result<[u8; 32]> {
+    let mut key = [0; 32];
+    match kdf {
+        Kdf::Sha256 => {
+            key = sha256(
+                &mut dh::data::read_ref(password.as_bytes()),
+                0,
+                password.len() as u64,
+            )?;
+        }
+        Kdf::Pbkdf2 { iterations, salt } => {
+            pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, *iterations, &mut key);
+        }
+        Kdf::Argon2 {
+            memory,
+            iterations,
+            parallelism,
+            salt,
+        } => {
+            let params = Params::new(*memory, *iterations, *parallelism, Some(32))
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into(password.as_bytes(), salt, &mut key)
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        }
+    }
+    Ok(key)
+}
+
+pub fn hash(key: &[u8; 32]) -> Result<[u8; 32]> {
+    sha256(&mut dh::data::read_ref(key), 0, 32)
+}
@@ -1,10 +1,51 @@
+mod append;
+mod auth;
+mod checksum;
+mod cipher;
+mod compression;
+mod convert;
 mod create;
+mod edit;
+mod error;
+mod extension;
 mod extract;
+mod fs;
+mod key;
+mod merge;
 mod metadata;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod recover;
+mod run;
+#[cfg(feature = "tar")]
+mod tar;
 mod types;
+#[cfg(feature = "zip")]
+mod zip;
 
+pub use error::ArchiveError;
 pub use types::*;
 
-pub use create::{create, write_hash};
+#[cfg(feature = "tar")]
+pub use self::tar::{from_tar, to_tar};
+#[cfg(feature = "gzip")]
+pub use self::tar::{from_tar_gz, to_tar_gz};
+#[cfg(feature = "zip")]
+pub use self::zip::{from_zip, to_zip};
+pub use append::append;
+pub use checksum::rehash;
+pub use compression::entry_reader;
+pub use convert::convert;
+pub use create::{create, create_extended, find_main_file, write_hash};
+pub use edit::{remove, replace, replace_entry, set_main_file};
 pub use extract::extract;
-pub use metadata::{metadata, verify_integrity};
+pub use fs::{pack, unpack};
+pub use merge::merge;
+pub use metadata::{
+    metadata, metadata_with_limits, verify_detailed, verify_entry, verify_integrity,
+    verify_integrity_with_progress,
+};
+#[cfg(feature = "mmap")]
+pub use mmap::MmapArchive;
+pub use recover::{recover, repair};
+pub use run::{prepare_run, run};
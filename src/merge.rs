@@ -0,0 +1,107 @@
+use crate::{create, metadata, ArchiveWithPassword, ConflictPolicy};
+use dh::{Readable, Rw};
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Result},
+};
+
+/// Writes a new archive containing the entries of all `sources`.
+///
+/// The sources can be of any version, encrypted ones are decrypted with their
+/// password. The main file is taken from the source at index `main_source`
+/// and looked up by path, so it may come from another source if that source
+/// won a conflict over its path. Like [`create`](crate::create), this returns
+/// the checksum to be written with [`write_hash`](crate::write_hash).
+pub fn merge<'a>(
+    version: u8,
+    mut sources: Vec<ArchiveWithPassword<'a>>,
+    encryption: Option<(&str, &[u8; 16])>,
+    main_source: Option<usize>,
+    conflict_policy: ConflictPolicy,
+    target: &'a mut dyn Rw<'a>,
+    buffer_size: u64,
+) -> Result<(u64, u32)> {
+    if main_source.is_some_and(|i| i >= sources.len()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Main source is out of range",
+        ));
+    }
+
+    let mut metas = Vec::new();
+    let mut decrypted = Vec::new();
+    for (i, source) in sources.iter_mut().enumerate() {
+        let mut meta = metadata(&mut *source.0, source.1)?;
+        decrypted.push(match &mut meta.encryption {
+            Some(_) if source.1.is_none() => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Archive {} is encrypted, but has no password", i),
+                ))
+            }
+            Some(encryption) if encryption.hash != encryption.hash_expected => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Wrong password for archive {}", i),
+                ))
+            }
+            Some(encryption) => Some(dh::data::read(std::mem::take(&mut encryption.decrypted))),
+            None => meta.decompressed.take().map(dh::data::read),
+        });
+        metas.push(meta);
+    }
+
+    let mut entries = Vec::new();
+    let mut positions = HashMap::new();
+    for (i, meta) in metas.iter().enumerate() {
+        for file in &meta.files {
+            let Some(&position) = positions.get(file.path.as_str()) else {
+                positions.insert(file.path.as_str(), entries.len());
+                entries.push((file, i));
+                continue;
+            };
+            if file.directory && entries[position].0.directory {
+                continue;
+            }
+            match conflict_policy {
+                ConflictPolicy::FirstWins => {}
+                ConflictPolicy::LastWins => entries[position] = (file, i),
+                ConflictPolicy::Error => {
+                    return Err(Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!("Path {:?} exists in several archives", file.path),
+                    ))
+                }
+            }
+        }
+    }
+
+    let main_file = main_source
+        .and_then(|i| {
+            let meta = &metas[i];
+            meta.main_file
+                .and_then(|main_file| meta.files.get(main_file as usize))
+        })
+        .map(|file| positions[file.path.as_str()] as u32);
+
+    let mut readers: Vec<&mut dyn Readable> = sources
+        .iter_mut()
+        .zip(decrypted.iter_mut())
+        .map(|(source, decrypted)| match decrypted {
+            Some(decrypted) => decrypted as &mut dyn Readable,
+            None => &mut *source.0,
+        })
+        .collect();
+
+    create::write(
+        version,
+        &entries,
+        &mut readers,
+        true,
+        encryption,
+        main_file,
+        None,
+        target,
+        buffer_size,
+    )
+}
@@ -1,58 +1,160 @@
-use crate::{Encryption, File, Metadata};
-use acr::{
-    encryption::aes256cbc,
-    hash::{murmur3, sha256},
+use crate::{
+    auth, checksum, cipher, compression, create,
+    extension::{self, Section},
+    key, ArchiveError, Compression, Encryption, Entry, EntryIntegrity, File, IntegrityReport,
+    Limits, Link, Metadata,
 };
+use acr::hash::crc32;
 use dh::{recommended::*, Readable};
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
+
+const BUFFER_SIZE: u64 = 65536;
 
 pub fn verify_integrity<'a>(reader: &'a mut dyn Readable<'a>, meta: &Metadata) -> Result<bool> {
     let hash = meta.checksum;
-    let offset = if meta.version > 2 { 128 } else { 64 };
+    let offset = checksum::header_size(meta.version);
     let size = reader.size()?;
+    if size < offset {
+        return Ok(false);
+    }
 
-    let calculated = murmur3(reader, offset, size - offset, 0x31082007)?;
+    let calculated = checksum::compute(reader, offset, size - offset)?;
     Ok(calculated == hash)
 }
 
-pub fn metadata<'a>(reader: &'a mut dyn Readable<'a>, password: Option<&str>) -> Result<Metadata> {
-    let mut version = if reader.read_bytes(4)? == b"SFA\0" {
-        1
-    } else {
-        2
+/// Like [`verify_integrity`], but reads the archive in chunks of `chunk_size`
+/// bytes and reports progress after each of them.
+///
+/// `progress` is called with the number of bytes processed so far and the
+/// total number of bytes covered by the checksum. Returning `false` cancels
+/// the verification, in which case `None` is returned.
+pub fn verify_integrity_with_progress(
+    reader: &mut dyn Readable,
+    meta: &Metadata,
+    chunk_size: u64,
+    progress: &mut dyn FnMut(u64, u64) -> bool,
+) -> Result<Option<bool>> {
+    let offset = checksum::header_size(meta.version);
+    let size = reader.size()?;
+    if size < offset {
+        return Ok(Some(false));
+    }
+
+    let total = size - offset;
+    let calculated =
+        checksum::compute_with_progress(reader, offset, total, chunk_size, &mut |processed| {
+            progress(processed, total)
+        })?;
+    Ok(calculated.map(|calculated| calculated == meta.checksum))
+}
+
+/// Like [`verify_integrity`], but also checks every entry to locate damage.
+///
+/// Entry data can only be verified if the archive stores per-entry checksums,
+/// the zero padding after each entry is always checked.
+pub fn verify_detailed<'a>(
+    reader: &'a mut dyn Readable<'a>,
+    meta: &Metadata,
+) -> Result<IntegrityReport> {
+    let entries = match meta.body() {
+        Some(body) => verify_entries(&mut dh::data::read_ref(body), &meta.files)?,
+        None => verify_entries(reader, &meta.files)?,
     };
+
+    Ok(IntegrityReport {
+        checksum: verify_integrity(reader, meta)?,
+        entries,
+    })
+}
+
+/// Verifies a single entry against its checksum.
+///
+/// Returns `None` if the archive does not store per-entry checksums.
+/// For encrypted archives, `reader` has to read from [`Encryption::decrypted`],
+/// for archives with a compressed body from [`Metadata::decompressed`].
+pub fn verify_entry(reader: &mut dyn Readable, file: &Entry) -> Result<Option<bool>> {
+    match file.checksum {
+        Some(checksum) => Ok(Some(
+            crc32(reader, &file.offset, &file.length, &BUFFER_SIZE)? == checksum,
+        )),
+        None => Ok(None),
+    }
+}
+
+fn verify_entries(body: &mut dyn Readable, files: &[Entry]) -> Result<Vec<EntryIntegrity>> {
+    let mut entries = Vec::new();
+
+    for file in files {
+        let padding_offset = file.offset + file.length;
+        let padding_length = file.path.len() as u64 + if file.directory { 2 } else { 0 };
+
+        entries.push(match verify_entry(body, file)? {
+            Some(false) => EntryIntegrity::Corrupted {
+                offset: file.offset,
+                length: file.length,
+            },
+            // The padding of a duplicate follows its own, empty data.
+            _ if file.duplicate_of.is_none()
+                && body
+                    .read_bytes_at(padding_offset, padding_length)?
+                    .iter()
+                    .any(|&b| b != 0) =>
+            {
+                EntryIntegrity::Corrupted {
+                    offset: padding_offset,
+                    length: padding_length,
+                }
+            }
+            Some(true) => EntryIntegrity::Valid,
+            None => EntryIntegrity::Unverified,
+        });
+    }
+
+    Ok(entries)
+}
+
+pub fn metadata(reader: &mut dyn Readable, password: Option<&str>) -> Result<Metadata> {
+    metadata_with_limits(reader, password, &Limits::default())
+}
+
+/// Like [`metadata`], but rejects archives exceeding the given limits.
+pub fn metadata_with_limits(
+    reader: &mut dyn Readable,
+    password: Option<&str>,
+    limits: &Limits,
+) -> Result<Metadata> {
+    let version = detect_version(reader)?;
+    reader.to(4)?;
     let checksum = reader.read_u32le()?;
     let file_count = reader.read_u32le()?;
     let pwd_hash: [u8; 32] = reader.read_bytes(32)?.try_into().unwrap();
     let iv: [u8; 16] = reader.read_bytes(16)?.try_into().unwrap();
     let main = reader.read_u32le()?;
+    reader.to(checksum::header_size(version))?;
 
-    if version == 2 {
-        let pos_before = reader.pos()?;
-        let p1 = reader.read_u128le();
-        if let Ok(p1) = p1 {
-            if p1 == 0 {
-                let p2 = reader.read_u128le();
-                if let Ok(p2) = p2 {
-                    if p2 == 0 {
-                        version = 3;
-                    } else {
-                        reader.to(pos_before)?;
-                    }
-                } else {
-                    reader.to(pos_before)?;
-                }
-            } else {
-                reader.to(pos_before)?;
-            }
-        } else {
-            reader.to(pos_before)?;
-        }
-    }
+    let marker = if version == 3 {
+        extension::read_marker(reader)?
+    } else {
+        None
+    };
+    let section = match marker {
+        Some((offset, length)) => Some(extension::read(reader, offset, length, limits)?),
+        None => None,
+    };
+    let extensions = section.as_ref().map(|s| s.extensions.clone());
+    let body_end = match marker {
+        Some((offset, _)) => offset,
+        None => reader.size()?,
+    };
 
     let encrypted = !(pwd_hash == [0; 32] && iv == [0; 16]);
 
+    let body_compression = extensions
+        .as_ref()
+        .map(|e| e.body_compression)
+        .unwrap_or_default();
     let mut decrypted_reader = None;
+    let mut decompressed_reader = None;
     let body: &mut dyn Readable = if encrypted {
         if password.is_none() {
             return Ok(Metadata {
@@ -66,18 +168,16 @@ pub fn metadata<'a>(reader: &'a mut dyn Readable<'a>, password: Option<&str>) ->
                 }),
                 files: vec![],
                 main_file: None,
+                extensions,
+                decompressed: None,
             });
         }
 
         let password = password.unwrap();
 
-        let key = sha256(
-            &mut dh::data::read_ref(password.as_bytes()),
-            0,
-            password.len() as u64,
-        )?;
-
-        let hash = sha256(&mut dh::data::read_ref(&key), 0, 32)?;
+        let kdf = extensions.as_ref().map(|e| e.kdf).unwrap_or_default();
+        let key = key::derive(password, &kdf)?;
+        let hash = key::hash(&key)?;
 
         if hash != pwd_hash {
             return Ok(Metadata {
@@ -91,39 +191,143 @@ pub fn metadata<'a>(reader: &'a mut dyn Readable<'a>, password: Option<&str>) ->
                 }),
                 files: vec![],
                 main_file: None,
+                extensions,
+                decompressed: None,
             });
         }
 
+        match section.as_ref().and_then(|s| s.mac) {
+            Some((mac, mac_pos)) if !auth::verify(reader, &key, mac_pos, &mac)? => {
+                return Err(ArchiveError::AuthenticationFailed.into());
+            }
+            // Without this, a MAC could simply be stripped from an archive.
+            None if limits.require_authentication => {
+                return Err(ArchiveError::Unauthenticated.into());
+            }
+            _ => {}
+        }
+
         let pos = reader.pos()?;
-        let size = reader.size()? - pos;
-        let decrypted = aes256cbc::decrypt(reader, &key, &iv, pos, size)?;
-        decrypted_reader = Some(dh::data::read(decrypted));
+        if body_end < pos {
+            return Err(ArchiveError::TruncatedCiphertext.into());
+        }
+        let decrypted = cipher::decrypt(reader, &key, &iv, pos, body_end - pos)?;
+        decrypted_reader = Some(dh::data::read(decompress(
+            section.as_ref(),
+            decrypted,
+            limits,
+        )?));
         decrypted_reader.as_mut().unwrap()
+    } else if body_compression != Compression::None {
+        let pos = reader.pos()?;
+        let stored = reader.read_bytes_at(pos, body_end.saturating_sub(pos))?;
+        decompressed_reader = Some(dh::data::read(decompress(
+            section.as_ref(),
+            stored,
+            limits,
+        )?));
+        decompressed_reader.as_mut().unwrap()
     } else {
         reader
     };
+    let body_end = if encrypted || body_compression != Compression::None {
+        body.size()?
+    } else {
+        body_end
+    };
 
-    let mut files = Vec::new();
+    // Entry checksums stored in the body follow the last entry.
+    let mut checksums = section.as_ref().and_then(|s| s.checksums.clone());
+    let body_end = if section.as_ref().is_some_and(|s| s.body_checksums) {
+        let table_pos = body_end
+            .checked_sub(file_count as u64 * 4)
+            .ok_or(ArchiveError::OutOfBounds)?;
+        let mut table = dh::data::read(body.read_bytes_at(table_pos, body_end - table_pos)?);
+        checksums = Some(
+            (0..file_count)
+                .map(|_| table.read_u32le())
+                .collect::<Result<_>>()?,
+        );
+        table_pos
+    } else {
+        body_end
+    };
 
-    for _ in 0..file_count {
-        let size = body.read_u64le()?;
-        let path_length = body.read_u16le()?;
-        let path = body.read_utf8(path_length as u64)?;
-        let directory = path.starts_with("//");
-        let offset = body.pos()?;
-        body.jump(size as i64 + path_length as i64)?;
-        files.push(File {
-            path: if directory {
-                path.strip_prefix("//").unwrap().to_string()
-            } else {
-                path
-            },
-            directory,
-            offset,
-            length: size,
-        });
+    // With a matching password hash and valid padding, an inconsistent body
+    // means the ciphertext itself is damaged.
+    let mut files = read_entries(body, body_end, file_count, limits).map_err(|e| {
+        match e.get_ref().and_then(|e| e.downcast_ref::<ArchiveError>()) {
+            Some(ArchiveError::OutOfBounds) | None if encrypted => {
+                ArchiveError::Undecryptable.into()
+            }
+            _ => e,
+        }
+    })?;
+
+    if let Some(links) = section.as_ref().and_then(|s| s.links.as_ref()) {
+        read_links(body, &mut files, links, limits)?;
+    }
+
+    if let Some(checksums) = checksums {
+        if checksums.len() != files.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Entry checksum count does not match the file count",
+            ));
+        }
+        for (file, checksum) in files.iter_mut().zip(checksums) {
+            file.checksum = Some(checksum);
+        }
+    }
+
+    if let Some(compression) = section.as_ref().and_then(|s| s.compression.as_ref()) {
+        if compression.len() != files.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Compression entry count does not match the file count",
+            ));
+        }
+        let mut total_size: u64 = 0;
+        for (file, (compression, uncompressed_length)) in files.iter_mut().zip(compression) {
+            if *compression != Compression::None {
+                file.compression = *compression;
+                file.uncompressed_length = *uncompressed_length;
+            }
+            total_size = total_size.saturating_add(file.uncompressed_length);
+        }
+        if total_size > limits.max_total_size {
+            return Err(ArchiveError::TooLarge.into());
+        }
+    }
+
+    if let Some(attributes) = section.as_ref().and_then(|s| s.attributes.as_ref()) {
+        if attributes.len() != files.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Attribute count does not match the file count",
+            ));
+        }
+        for (file, attributes) in files.iter_mut().zip(attributes) {
+            file.attributes = *attributes;
+        }
+    }
+
+    if let Some(duplicates) = section.as_ref().and_then(|s| s.duplicates.as_ref()) {
+        resolve_duplicates(&mut files, duplicates)?;
     }
 
+    // Archives written before the main file was validated can point it at
+    // anything, which only makes the main file unusable.
+    let main_file = main.checked_sub(1).filter(|&main_file| {
+        create::validate_main_file(
+            main_file,
+            files.get(main_file as usize),
+            files.len(),
+            ErrorKind::InvalidData,
+        )
+        .is_ok()
+    });
+
     Ok(Metadata {
         version,
         checksum,
@@ -138,6 +342,162 @@ pub fn metadata<'a>(reader: &'a mut dyn Readable<'a>, password: Option<&str>) ->
             None
         },
         files,
-        main_file: if main > 0 { Some(main - 1) } else { None },
+        main_file,
+        extensions,
+        decompressed: decompressed_reader.map(dh::data::close),
     })
 }
+
+/// Reads the targets of link entries, which are stored as their data.
+fn read_links(
+    body: &mut dyn Readable,
+    files: &mut [Entry],
+    links: &[(u32, bool)],
+    limits: &Limits,
+) -> Result<()> {
+    for &(entry, hard) in links {
+        let file = match files.get_mut(entry as usize) {
+            Some(file) if !file.directory && file.link.is_none() => file,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid link entry")),
+        };
+        if file.length > limits.max_path_length as u64 {
+            return Err(ArchiveError::PathTooLong.into());
+        }
+        let target = String::from_utf8(body.read_bytes_at(file.offset, file.length)?)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Link target is not valid UTF-8"))?;
+        file.link = Some(if hard {
+            Link::Hard(target)
+        } else {
+            Link::Symbolic(target)
+        });
+    }
+    Ok(())
+}
+
+/// Points entries stored without data at the data of the entry they duplicate.
+///
+/// Duplicates are listed in order, so an original that is a duplicate itself
+/// has already been resolved.
+fn resolve_duplicates(files: &mut [Entry], duplicates: &[(u32, u32)]) -> Result<()> {
+    let mut previous = None;
+    for &(entry, original) in duplicates {
+        let (entry, original) = (entry as usize, original as usize);
+        if previous.is_some_and(|previous| entry <= previous)
+            || original >= entry
+            || entry >= files.len()
+            || files[entry].directory
+            || files[entry].length != 0
+            || files[original].directory
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Invalid duplicate entry",
+            ));
+        }
+
+        let original_file = &files[original];
+        let (offset, length, checksum, compression, uncompressed_length) = (
+            original_file.offset,
+            original_file.length,
+            original_file.checksum,
+            original_file.compression,
+            original_file.uncompressed_length,
+        );
+        let file = &mut files[entry];
+        file.offset = offset;
+        file.length = length;
+        file.checksum = checksum;
+        file.compression = compression;
+        file.uncompressed_length = uncompressed_length;
+        file.duplicate_of = Some(original as u32);
+        previous = Some(entry);
+    }
+    Ok(())
+}
+
+/// Decompresses the body if the archive uses body compression.
+fn decompress(section: Option<&Section>, body: Vec<u8>, limits: &Limits) -> Result<Vec<u8>> {
+    let Some(section) = section.filter(|s| s.extensions.body_compression != Compression::None)
+    else {
+        return Ok(body);
+    };
+    if section.body_length > limits.max_total_size.min(limits.max_body_length) {
+        return Err(ArchiveError::TooLarge.into());
+    }
+    compression::decompress(
+        section.extensions.body_compression,
+        &body,
+        section.body_length,
+    )
+}
+
+/// Detects the version from the header.
+///
+/// Version 3 is told apart from version 2 by the first half of its reserved
+/// header space, which is zeroed unless it guards against legacy readers.
+pub(crate) fn detect_version(reader: &mut dyn Readable) -> Result<u8> {
+    if reader.read_bytes_at(0, 4)? == b"SFA\0" {
+        return Ok(1);
+    }
+    match reader.read_bytes_at(64, 32) {
+        Ok(reserved) if reserved.iter().all(|&b| b == 0) || extension::is_guard(&reserved) => Ok(3),
+        _ => Ok(2),
+    }
+}
+
+fn read_entries(
+    body: &mut dyn Readable,
+    body_end: u64,
+    file_count: u32,
+    limits: &Limits,
+) -> Result<Vec<Entry>> {
+    if file_count > limits.max_entries {
+        return Err(ArchiveError::TooManyEntries.into());
+    }
+    // Every entry takes at least its 10 byte header.
+    if file_count as u64 * 10 > body_end.saturating_sub(body.pos()?) {
+        return Err(ArchiveError::OutOfBounds.into());
+    }
+
+    let mut files = Vec::new();
+    let mut total_size: u64 = 0;
+
+    for _ in 0..file_count {
+        if body_end.saturating_sub(body.pos()?) < 10 {
+            return Err(ArchiveError::OutOfBounds.into());
+        }
+        let size = body.read_u64le()?;
+        let path_length = body.read_u16le()?;
+        if path_length > limits.max_path_length {
+            return Err(ArchiveError::PathTooLong.into());
+        }
+        let remaining = body_end - body.pos()?;
+        if size
+            .checked_add(2 * path_length as u64)
+            .is_none_or(|needed| needed > remaining)
+        {
+            return Err(ArchiveError::OutOfBounds.into());
+        }
+        total_size = total_size.saturating_add(size);
+        if total_size > limits.max_total_size {
+            return Err(ArchiveError::TooLarge.into());
+        }
+
+        let path = body.read_utf8(path_length as u64)?;
+        let directory = path.starts_with("//");
+        let offset = body.pos()?;
+        body.to(offset + size + path_length as u64)?;
+        files.push(Entry::from(File {
+            path: if directory {
+                path.strip_prefix("//").unwrap().to_string()
+            } else {
+                path
+            },
+            directory,
+            offset,
+            length: size,
+        }));
+    }
+
+    Ok(files)
+}
@@ -0,0 +1,115 @@
+use crate::{checksum, metadata_with_limits, verify_entry, Compression, Entry, Limits, Metadata};
+use memmap2::Mmap;
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+};
+
+/// An archive mapped into memory, whose entries can be borrowed without
+/// copying them.
+#[derive(Debug)]
+pub struct MmapArchive {
+    map: Mmap,
+    meta: Metadata,
+}
+
+impl MmapArchive {
+    /// Maps `file` and reads its metadata, see [`metadata`](crate::metadata).
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated, by this or any other
+    /// process, until the archive is dropped, as the mapped bytes would
+    /// change underneath the borrowed slices. A lock or a file nobody else
+    /// can write to is needed to ensure that.
+    pub unsafe fn open(file: &fs::File, password: Option<&str>) -> Result<Self> {
+        // SAFETY: Upheld by the caller.
+        unsafe { Self::open_with_limits(file, password, &Limits::default()) }
+    }
+
+    /// Like [`MmapArchive::open`], but rejects archives exceeding the given
+    /// limits, see [`metadata_with_limits`].
+    ///
+    /// # Safety
+    ///
+    /// The same as for [`MmapArchive::open`].
+    pub unsafe fn open_with_limits(
+        file: &fs::File,
+        password: Option<&str>,
+        limits: &Limits,
+    ) -> Result<Self> {
+        // SAFETY: The caller guarantees the file is not modified while it is
+        // mapped.
+        let map = unsafe { Mmap::map(file)? };
+        let meta = metadata_with_limits(&mut dh::data::read_ref(&map), password, limits)?;
+        Ok(Self { map, meta })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.meta
+    }
+
+    /// The whole mapped archive.
+    pub fn bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// The stored data of an entry of this archive.
+    ///
+    /// Entries of unencrypted archives are borrowed from the mapped file,
+    /// those of encrypted or body-compressed archives from the body held by
+    /// the metadata. Compressed entries have to be read with
+    /// [`entry_reader`](crate::entry_reader) instead.
+    pub fn entry(&self, file: &Entry) -> Result<&[u8]> {
+        if file.compression != Compression::None {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Entry {:?} is compressed", file.path),
+            ));
+        }
+        let body = self.meta.body().unwrap_or(&self.map);
+        file.offset
+            .checked_add(file.length)
+            .and_then(|end| body.get(file.offset as usize..end as usize))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Entry {:?} is not part of the archive", file.path),
+                )
+            })
+    }
+
+    /// The stored data of the entry at `path`, see [`MmapArchive::entry`].
+    pub fn entry_by_path(&self, path: &str) -> Result<&[u8]> {
+        let file = self
+            .meta
+            .files
+            .iter()
+            .find(|file| file.path == path && !file.directory)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("File {:?} is not in the archive", path),
+                )
+            })?;
+        self.entry(file)
+    }
+
+    /// Verifies the checksum over the mapped bytes, see
+    /// [`verify_integrity`](crate::verify_integrity).
+    pub fn verify_integrity(&self) -> bool {
+        let offset = checksum::header_size(self.meta.version) as usize;
+        self.map
+            .get(offset..)
+            .is_some_and(|body| checksum::compute_bytes(body) == self.meta.checksum)
+    }
+
+    /// Verifies the checksum of a single entry, see
+    /// [`verify_entry`](crate::verify_entry).
+    pub fn verify_entry(&self, file: &Entry) -> Result<Option<bool>> {
+        match self.meta.body() {
+            Some(body) => verify_entry(&mut dh::data::read_ref(body), file),
+            None => verify_entry(&mut dh::data::read_ref(&self.map), file),
+        }
+    }
+}
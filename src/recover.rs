@@ -0,0 +1,223 @@
+use crate::{
+    checksum, create,
+    extension::{self, Section},
+    metadata::detect_version,
+    Compression, Entry, File, Limits, Link, Recovery,
+};
+use dh::{recommended::*, Readable, Rw};
+use std::io::{Error, ErrorKind, Result};
+
+/// Scans an unencrypted archive for every entry that is still consistent.
+///
+/// Instead of trusting the file count, the body is walked entry by entry.
+/// Wherever an entry does not make sense, the scan moves on byte by byte
+/// until it finds the next plausible entry header: a non-empty UTF-8 path
+/// followed by data that fits into the body and zeroed padding.
+///
+/// The extension section records attributes and links by entry index, so
+/// they are only kept for the entries before the first damaged range.
+pub fn recover(reader: &mut dyn Readable) -> Result<Recovery> {
+    let size = reader.size()?;
+    if size < 64 {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Archive is too short to contain a header",
+        ));
+    }
+
+    let version = detect_version(reader)?;
+    if reader.read_bytes_at(12, 48)?.iter().any(|&b| b != 0) {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "Recovering encrypted archives is not supported",
+        ));
+    }
+    let main = reader.read_u32le_at(60)?;
+
+    let mut pos = checksum::header_size(version);
+    // A damaged extension section is simply scanned like the rest of the body.
+    let marker = match extension::read_marker(reader) {
+        Ok(marker) if version == 3 => marker,
+        _ => None,
+    };
+    let section = marker.and_then(|(offset, length)| {
+        extension::read(reader, offset, length, &Limits::default()).ok()
+    });
+    if let Some(section) = &section {
+        if section.extensions.body_compression != Compression::None {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Recovering archives with a compressed body is not supported",
+            ));
+        }
+        // The compression table is indexed by entry, which cannot be relied
+        // on once entries are lost.
+        if section.compression.is_some() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Recovering archives with compressed entries is not supported",
+            ));
+        }
+        // Duplicates would be recovered as empty files.
+        if section.duplicates.is_some() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Recovering deduplicated archives is not supported",
+            ));
+        }
+    }
+    let end = marker.map_or(size, |(offset, _)| offset);
+
+    let mut files = Vec::new();
+    let mut main_file = None;
+    let mut damaged = Vec::new();
+    let mut damage_start = None;
+
+    while pos < end {
+        match read_entry(reader, pos, end)? {
+            Some((file, next)) => {
+                if let Some(start) = damage_start.take() {
+                    damaged.push((start, pos - start));
+                }
+                if damaged.is_empty() && main == files.len() as u32 + 1 {
+                    main_file = Some(files.len() as u32);
+                }
+                files.push(file);
+                pos = next;
+            }
+            None => {
+                damage_start.get_or_insert(pos);
+                pos += 1;
+            }
+        }
+    }
+    if let Some(start) = damage_start {
+        damaged.push((start, end - start));
+    }
+
+    // Entries are only where the section says up to the first damage.
+    let trusted = match damaged.first() {
+        Some(&(start, _)) => files.iter().take_while(|file| file.offset < start).count(),
+        None => files.len(),
+    };
+    let dropped = match (&section, marker) {
+        (Some(section), _) => apply_section(reader, section, &mut files[..trusted])?,
+        // The section itself is damaged.
+        (None, Some((offset, length))) => {
+            damaged.push((offset, length as u64));
+            0
+        }
+        (None, None) => 0,
+    };
+
+    // Links cannot be the main file.
+    let main_file = main_file.filter(|&i| files[i as usize].link.is_none());
+
+    Ok(Recovery {
+        version,
+        files,
+        main_file,
+        damaged,
+        dropped,
+    })
+}
+
+/// Restores the attributes and links of `files`, which are the first entries
+/// of the archive, and returns how many of those recorded for other entries
+/// are dropped.
+fn apply_section(
+    reader: &mut dyn Readable,
+    section: &Section,
+    files: &mut [Entry],
+) -> Result<usize> {
+    let mut dropped = 0;
+    for (i, attributes) in section.attributes.iter().flatten().enumerate() {
+        match files.get_mut(i) {
+            Some(file) => file.attributes = *attributes,
+            None => dropped += attributes.is_some() as usize,
+        }
+    }
+    for &(i, hard) in section.links.iter().flatten() {
+        let file = match files.get_mut(i as usize) {
+            Some(file) if !file.directory && file.length <= u16::MAX as u64 => file,
+            _ => {
+                dropped += 1;
+                continue;
+            }
+        };
+        match String::from_utf8(reader.read_bytes_at(file.offset, file.length)?) {
+            Ok(target) if hard => file.link = Some(Link::Hard(target)),
+            Ok(target) => file.link = Some(Link::Symbolic(target)),
+            Err(_) => dropped += 1,
+        }
+    }
+    Ok(dropped)
+}
+
+/// Writes a new archive containing the entries found by [`recover`].
+///
+/// `source` has to be the archive that was recovered. Like [`create`], this
+/// returns the checksum to be written with [`write_hash`](crate::write_hash).
+pub fn repair<'a>(
+    source: &'a mut dyn Readable<'a>,
+    recovery: &Recovery,
+    target: &'a mut dyn Rw<'a>,
+    buffer_size: u64,
+) -> Result<(u64, u32)> {
+    let entries: Vec<_> = recovery.files.iter().map(|file| (file, 0)).collect();
+
+    create::write(
+        recovery.version,
+        &entries,
+        &mut [source],
+        true,
+        None,
+        recovery.main_file,
+        None,
+        target,
+        buffer_size,
+    )
+}
+
+fn read_entry(reader: &mut dyn Readable, pos: u64, end: u64) -> Result<Option<(Entry, u64)>> {
+    if end - pos < 10 {
+        return Ok(None);
+    }
+    let size = reader.read_u64le_at(pos)?;
+    let path_length = reader.read_u16le_at(pos + 8)? as u64;
+    if path_length == 0 {
+        return Ok(None);
+    }
+
+    let offset = pos + 10 + path_length;
+    let next = match size.checked_add(offset + path_length) {
+        Some(next) if next <= end => next,
+        _ => return Ok(None),
+    };
+    let Ok(path) = String::from_utf8(reader.read_bytes_at(pos + 10, path_length)?) else {
+        return Ok(None);
+    };
+    if path == "//"
+        || reader
+            .read_bytes_at(offset + size, path_length)?
+            .iter()
+            .any(|&b| b != 0)
+    {
+        return Ok(None);
+    }
+
+    let directory = path.starts_with("//");
+    Ok(Some((
+        Entry::from(File {
+            path: if directory {
+                path.strip_prefix("//").unwrap().to_string()
+            } else {
+                path
+            },
+            directory,
+            offset,
+            length: size,
+        }),
+        next,
+    )))
+}
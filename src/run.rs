@@ -0,0 +1,243 @@
+use crate::{fs::entry_path, unpack, AttributePolicy, Launch, LinkPolicy, Metadata};
+use dh::Readable;
+use std::{
+    env, fs,
+    io::{Error, ErrorKind, Read, Result},
+    path::{Path, PathBuf},
+    process::{self, Command, ExitStatus},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Unpacks an archive so its main file can be run.
+///
+/// The archive is unpacked into `directory`, or into a new directory inside
+/// the system's temporary directory if it is `None`. Like for
+/// [`unpack`](crate::unpack), `source` has to be the archive `meta` was read
+/// from, with the right password if it is encrypted. Ownership and the setuid
+/// and setgid bits are not restored.
+pub fn prepare_run(
+    source: &mut dyn Readable,
+    meta: &Metadata,
+    directory: Option<&Path>,
+    buffer_size: u64,
+) -> Result<Launch> {
+    if meta
+        .encryption
+        .as_ref()
+        .is_some_and(|e| e.hash != e.hash_expected)
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Archive has not been decrypted",
+        ));
+    }
+    let main_path = meta
+        .main_path()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Archive has no main file"))?;
+
+    let (directory, temporary) = match directory {
+        Some(directory) => (directory.to_path_buf(), false),
+        None => (temporary_directory()?, true),
+    };
+    let launch = Launch {
+        main_file: entry_path(&directory, main_path)?,
+        directory,
+        temporary,
+    };
+    if let Err(e) = unpack(
+        source,
+        meta,
+        &launch.directory,
+        LinkPolicy::Create,
+        AttributePolicy::Permissions,
+        buffer_size,
+    ) {
+        // The unpack error is more useful than one from removing what it
+        // left behind.
+        let _ = launch.cleanup();
+        return Err(e);
+    }
+    Ok(launch)
+}
+
+/// Unpacks an archive into a temporary directory, runs its main file with
+/// `args` and waits for it to exit, see [`prepare_run`] and
+/// [`Launch::command`].
+///
+/// The main file has to be a script or an executable. Other files are opened
+/// by an application that may still be starting when the opener exits, so
+/// they fail with [`ErrorKind::Unsupported`] instead of being removed while
+/// in use. The temporary directory is removed afterwards. The exit status is
+/// returned even if that fails, as the main file has run by then.
+pub fn run(
+    source: &mut dyn Readable,
+    meta: &Metadata,
+    args: &[&str],
+    buffer_size: u64,
+) -> Result<ExitStatus> {
+    let launch = prepare_run(source, meta, None, buffer_size)?;
+    let status = match launch.direct_command(args) {
+        Ok(Some(mut command)) => command.status(),
+        Ok(None) => Err(Error::new(
+            ErrorKind::Unsupported,
+            "Main file is neither a script nor an executable",
+        )),
+        Err(e) => Err(e),
+    };
+    let _ = launch.cleanup();
+    status
+}
+
+impl Launch {
+    /// Builds the command that runs the main file with `args`, in
+    /// [`Launch::directory`].
+    ///
+    /// Scripts starting with `#!` are passed to their interpreter, which
+    /// works without the executable bit. Executables are run directly. Any
+    /// other file is opened with the system's default application, which
+    /// does not receive `args`.
+    pub fn command(&self, args: &[&str]) -> Result<Command> {
+        if let Some(command) = self.direct_command(args)? {
+            return Ok(command);
+        }
+        let mut command = open(&self.main_file);
+        command.current_dir(&self.directory);
+        Ok(command)
+    }
+
+    /// Builds the command for a script or an executable main file, which
+    /// runs until the main file exits.
+    fn direct_command(&self, args: &[&str]) -> Result<Option<Command>> {
+        let mut command = if let Some((interpreter, argument)) = shebang(&self.main_file)? {
+            let mut command = Command::new(interpreter);
+            command.args(argument).arg(&self.main_file).args(args);
+            command
+        } else if is_executable(&self.main_file)? {
+            let mut command = Command::new(&self.main_file);
+            command.args(args);
+            command
+        } else {
+            return Ok(None);
+        };
+        command.current_dir(&self.directory);
+        Ok(Some(command))
+    }
+
+    /// Removes [`Launch::directory`] if it was created by
+    /// [`prepare_run`](crate::prepare_run).
+    pub fn cleanup(self) -> Result<()> {
+        if self.temporary {
+            // Restored permissions can make directories read-only, which
+            // keeps their contents from being removed.
+            make_writable(&self.directory)?;
+            fs::remove_dir_all(&self.directory)?;
+        }
+        Ok(())
+    }
+}
+
+/// Makes `directory` and every directory inside it writable by the owner.
+fn make_writable(directory: &Path) -> Result<()> {
+    let mut permissions = fs::metadata(directory)?.permissions();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o700);
+    }
+    #[cfg(not(unix))]
+    permissions.set_readonly(false);
+    fs::set_permissions(directory, permissions)?;
+
+    for child in fs::read_dir(directory)? {
+        let child = child?;
+        if child.file_type()?.is_dir() {
+            make_writable(&child.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates a new directory only accessible by the current user.
+fn temporary_directory() -> Result<PathBuf> {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    loop {
+        let path = env::temp_dir().join(format!(
+            "hssp-run-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        match builder.create(&path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads the interpreter and its optional argument from a `#!` line.
+///
+/// Like Linux, everything after the interpreter is a single argument.
+fn shebang(path: &Path) -> Result<Option<(String, Option<String>)>> {
+    let mut head = Vec::new();
+    fs::File::open(path)?.take(256).read_to_end(&mut head)?;
+    let Some(line) = head.strip_prefix(b"#!") else {
+        return Ok(None);
+    };
+    let line = line.split(|&b| b == b'\n').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(match line.split_once([' ', '\t']) {
+        Some((interpreter, argument)) => (
+            interpreter.to_string(),
+            Some(argument.trim().to_string()).filter(|a| !a.is_empty()),
+        ),
+        None => (line.to_string(), None),
+    }))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(fs::metadata(path)?.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(windows)]
+fn is_executable(path: &Path) -> Result<bool> {
+    Ok(path.extension().is_some_and(|extension| {
+        ["exe", "com", "bat", "cmd"]
+            .iter()
+            .any(|e| extension.eq_ignore_ascii_case(e))
+    }))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_executable(_path: &Path) -> Result<bool> {
+    Ok(false)
+}
+
+#[cfg(target_os = "macos")]
+fn open(path: &Path) -> Command {
+    let mut command = Command::new("open");
+    command.arg(path);
+    command
+}
+
+#[cfg(windows)]
+fn open(path: &Path) -> Command {
+    let mut command = Command::new("cmd");
+    command.args(["/C", "start", ""]).arg(path);
+    command
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn open(path: &Path) -> Command {
+    let mut command = Command::new("xdg-open");
+    command.arg(path);
+    command
+}
@@ -0,0 +1,275 @@
+use crate::{
+    checksum, create, entry_reader,
+    extension::{self, Section},
+    Attributes, Entry, File, Link, Metadata, SkippedEntry, SkippedKind,
+};
+use ::tar::{Archive, Builder, EntryType, Header};
+use dh::{recommended::*, Readable, Rw};
+use std::io::{copy, BufReader, Error, ErrorKind, Read, Result, Write};
+
+/// Converts a tar archive into an unencrypted HSSP archive in a single pass.
+///
+/// Tar directory entries become HSSP directories. For version 3, symbolic
+/// and hard links become links and the mode, modification time and owner of
+/// every entry are kept as its attributes. Entries HSSP has no equivalent
+/// for, like device nodes and links in older versions, are skipped and
+/// returned. The first value is the checksum to be written with
+/// [`write_hash`](crate::write_hash), like [`create`](crate::create) returns it.
+pub fn from_tar(
+    reader: &mut dyn Read,
+    version: u8,
+    target: &mut dyn Rw,
+) -> Result<((u64, u32), Vec<SkippedEntry>)> {
+    // The file count is only known at the end.
+    let hash_pos = create::write_header(target, version, 0, &[0; 32], &[0; 16], None)?;
+    let body_pos = target.pos()?;
+    let mut file_count: u32 = 0;
+    let mut skipped = Vec::new();
+    let mut attributes = Vec::new();
+    let mut links = Vec::new();
+
+    let mut archive = Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_pax_global_extensions() {
+            continue;
+        }
+
+        let path = utf8(entry.path_bytes().into_owned(), "path")?;
+        let path = normalize(&path);
+        // The archive root has no HSSP entry.
+        if path.is_empty() || path == "." {
+            continue;
+        }
+
+        let (directory, link) = match entry_type {
+            EntryType::Directory => (true, None),
+            EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => (false, None),
+            EntryType::Symlink | EntryType::Link if version >= 3 => {
+                let target = utf8(
+                    entry.link_name_bytes().unwrap_or_default().into_owned(),
+                    "link target",
+                )?;
+                if entry_type == EntryType::Symlink {
+                    (false, Some(Link::Symbolic(target)))
+                } else {
+                    (false, Some(Link::Hard(normalize(&target).to_string())))
+                }
+            }
+            _ => {
+                skipped.push(SkippedEntry {
+                    path: path.to_string(),
+                    kind: skipped_kind(entry_type),
+                });
+                continue;
+            }
+        };
+
+        // The data of a link is its target.
+        let length = link.as_ref().map_or(0, |link| link.target().len() as u64);
+        let mut file = Entry {
+            link,
+            ..Entry::from(File {
+                path: path.to_string(),
+                directory,
+                length,
+                ..Default::default()
+            })
+        };
+        create::validate(&file)?;
+        if version >= 3 {
+            attributes.push(Some(capture(entry.header())?));
+            if let Some(link) = &file.link {
+                links.push((file_count, matches!(link, Link::Hard(_))));
+            }
+        }
+        file_count = file_count
+            .checked_add(1)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Too many entries"))?;
+
+        if let Some(link) = &file.link {
+            let padding = create::write_entry_header(target, &file)?;
+            target.write_utf8(link.target())?;
+            target.write_bytes(&vec![0; padding])?;
+            continue;
+        }
+
+        // Sparse entries are longer than their header says, so the size is
+        // written once the data is copied.
+        let entry_pos = target.pos()?;
+        let padding = create::write_entry_header(target, &file)?;
+        if !directory {
+            file.length = copy(&mut entry, target)?;
+            target.write_u64le_at(entry_pos, file.length)?;
+        }
+        target.write_bytes(&vec![0; padding])?;
+    }
+
+    if version >= 3 && file_count > 0 {
+        extension::write(
+            target,
+            &Section {
+                attributes: Some(attributes),
+                links: (!links.is_empty()).then_some(links),
+                ..Default::default()
+            },
+        )?;
+    }
+
+    target.write_u32le_at(hash_pos + 4, file_count)?;
+    let body_size = target.pos()? - body_pos;
+    let hash = checksum::compute(Readable::as_trait(target), body_pos, body_size)?;
+
+    Ok(((hash_pos, hash), skipped))
+}
+
+/// Converts an HSSP archive into a tar archive.
+///
+/// `source` has to be the archive `meta` was read from. Encrypted archives
+/// are read from [`Encryption::decrypted`](crate::Encryption::decrypted), so
+/// `meta` has to be read with the right password, and archives with a
+/// compressed body from [`Metadata::decompressed`].
+pub fn to_tar(
+    source: &mut dyn Readable,
+    meta: &Metadata,
+    target: &mut dyn Write,
+    buffer_size: u64,
+) -> Result<()> {
+    let mut builder = Builder::new(target);
+    match &meta.encryption {
+        Some(encryption) if encryption.hash != encryption.hash_expected => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Archive has not been decrypted",
+            ))
+        }
+        Some(encryption) => write_files(
+            &mut builder,
+            &mut dh::data::read_ref(&encryption.decrypted),
+            &meta.files,
+            buffer_size,
+        )?,
+        None => match &meta.decompressed {
+            Some(body) => write_files(
+                &mut builder,
+                &mut dh::data::read_ref(body),
+                &meta.files,
+                buffer_size,
+            )?,
+            None => write_files(&mut builder, source, &meta.files, buffer_size)?,
+        },
+    }
+    builder.finish()
+}
+
+/// Like [`from_tar`], but for gzip compressed tar archives.
+#[cfg(feature = "gzip")]
+pub fn from_tar_gz(
+    reader: &mut dyn Read,
+    version: u8,
+    target: &mut dyn Rw,
+) -> Result<((u64, u32), Vec<SkippedEntry>)> {
+    from_tar(&mut flate2::read::GzDecoder::new(reader), version, target)
+}
+
+/// Like [`to_tar`], but compresses the tar archive with gzip.
+#[cfg(feature = "gzip")]
+pub fn to_tar_gz(
+    source: &mut dyn Readable,
+    meta: &Metadata,
+    target: &mut dyn Write,
+    buffer_size: u64,
+) -> Result<()> {
+    let mut encoder = flate2::write::GzEncoder::new(target, flate2::Compression::default());
+    to_tar(source, meta, &mut encoder, buffer_size)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+fn write_files<W: Write>(
+    builder: &mut Builder<W>,
+    body: &mut dyn Readable,
+    files: &[Entry],
+    buffer_size: u64,
+) -> Result<()> {
+    for file in files {
+        let mut header = Header::new_gnu();
+        header.set_mode(match (&file.attributes, &file.link) {
+            (Some(attributes), _) => attributes.mode,
+            (None, Some(_)) => 0o777,
+            (None, None) if file.directory => 0o755,
+            (None, None) => 0o644,
+        });
+        if let Some(attributes) = &file.attributes {
+            header.set_mtime(attributes.mtime.max(0) as u64);
+            header.set_uid(attributes.uid as u64);
+            header.set_gid(attributes.gid as u64);
+        }
+
+        if let Some(link) = &file.link {
+            header.set_entry_type(match link {
+                Link::Symbolic(_) => EntryType::Symlink,
+                Link::Hard(_) => EntryType::Link,
+            });
+            header.set_size(0);
+            builder.append_link(&mut header, &file.path, link.target())?;
+            continue;
+        }
+        if file.directory {
+            header.set_entry_type(EntryType::Directory);
+            header.set_size(0);
+            builder.append_data(&mut header, format!("{}/", file.path), std::io::empty())?;
+            continue;
+        }
+
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(file.uncompressed_length);
+        let data = BufReader::with_capacity(buffer_size as usize, entry_reader(body, file)?);
+        builder.append_data(&mut header, &file.path, data)?;
+    }
+
+    Ok(())
+}
+
+fn capture(header: &Header) -> Result<Attributes> {
+    let id = |id: u64| {
+        u32::try_from(id)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Tar entry owner is out of range"))
+    };
+    // Some writers leave the owner and time empty, which reads as zero.
+    Ok(Attributes {
+        mtime: header.mtime().unwrap_or(0).min(i64::MAX as u64) as i64,
+        mode: header.mode()? & 0o7777,
+        uid: id(header.uid().unwrap_or(0))?,
+        gid: id(header.gid().unwrap_or(0))?,
+    })
+}
+
+/// Strips leading `./` and trailing `/` from a tar path.
+fn normalize(mut path: &str) -> &str {
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    path.trim_end_matches('/')
+}
+
+fn utf8(bytes: Vec<u8>, what: &str) -> Result<String> {
+    String::from_utf8(bytes).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Tar entry {} is not valid UTF-8", what),
+        )
+    })
+}
+
+fn skipped_kind(entry_type: EntryType) -> SkippedKind {
+    match entry_type {
+        EntryType::Symlink => SkippedKind::Symlink,
+        EntryType::Link => SkippedKind::HardLink,
+        EntryType::Char => SkippedKind::CharacterDevice,
+        EntryType::Block => SkippedKind::BlockDevice,
+        EntryType::Fifo => SkippedKind::Fifo,
+        other => SkippedKind::Other(other.as_byte()),
+    }
+}
@@ -1,12 +1,53 @@
 use dh::Readable;
+use std::{
+    collections::BTreeMap,
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+};
 
 #[derive(Debug)]
 pub struct Metadata {
     pub version: u8,
     pub checksum: u32,
     pub encryption: Option<Encryption>,
-    pub files: Vec<File>,
+    pub files: Vec<Entry>,
+    /// The index of the main file, which is `None` if the stored index does
+    /// not point to a regular file.
     pub main_file: Option<u32>,
+    pub extensions: Option<Extensions>,
+    /// The body of an unencrypted archive with [`Extensions::body_compression`],
+    /// which entry offsets point into instead of the archive.
+    pub decompressed: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    /// The path of the main file, if the archive has one and could be read.
+    pub fn main_path(&self) -> Option<&str> {
+        Some(&self.files.get(self.main_file? as usize)?.path)
+    }
+
+    /// The decrypted or decompressed body entry offsets point into, or `None`
+    /// if they point into the archive itself.
+    pub fn body(&self) -> Option<&[u8]> {
+        match &self.encryption {
+            Some(encryption) => Some(&encryption.decrypted),
+            None => self.decompressed.as_deref(),
+        }
+    }
+
+    /// The comment stored in the extension section, see [`Extensions::comment`].
+    pub fn comment(&self) -> Option<&str> {
+        self.extensions.as_ref()?.comment.as_deref()
+    }
+
+    /// The properties stored in the extension section, see
+    /// [`Extensions::properties`].
+    pub fn properties(&self) -> &BTreeMap<String, String> {
+        static EMPTY: BTreeMap<String, String> = BTreeMap::new();
+        self.extensions
+            .as_ref()
+            .map_or(&EMPTY, |extensions| &extensions.properties)
+    }
 }
 
 #[derive(Debug)]
@@ -14,15 +55,314 @@ pub struct Encryption {
     pub hash: [u8; 32],
     pub hash_expected: [u8; 32],
     pub iv: [u8; 16],
+    /// The decrypted body, which is also decompressed if the archive uses
+    /// [`Extensions::body_compression`].
     pub decrypted: Vec<u8>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone)]
 pub struct File {
     pub path: String,
     pub directory: bool,
     pub offset: u64,
+    /// The number of bytes stored in the archive.
+    ///
+    /// When creating an archive, this is the length of the uncompressed data.
     pub length: u64,
 }
 
+/// A [`File`] together with what the extension section of a v3 archive
+/// records about it. Dereferences to its [`File`].
+#[derive(Debug, Default, Clone)]
+pub struct Entry {
+    pub file: File,
+    /// CRC32 of the entry's data, if the archive stores per-entry checksums.
+    pub checksum: Option<u32>,
+    /// How the data is compressed. When creating an archive, entries without
+    /// compression use [`Extensions::compression`].
+    pub compression: Compression,
+    /// The length of the data after decompressing it.
+    pub uncompressed_length: u64,
+    /// The index of the earlier entry this entry shares its data with, if
+    /// the archive is deduplicated. Ignored when creating an archive.
+    pub duplicate_of: Option<u32>,
+    /// Timestamps, permissions and ownership, which require version 3.
+    pub attributes: Option<Attributes>,
+    /// Makes the entry a link, which requires version 3. Its target is
+    /// stored as its data, so the data of a link is never read when creating
+    /// an archive.
+    pub link: Option<Link>,
+}
+
+impl From<File> for Entry {
+    fn from(file: File) -> Self {
+        Self {
+            uncompressed_length: file.length,
+            file,
+            ..Default::default()
+        }
+    }
+}
+
+impl Deref for Entry {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        &self.file
+    }
+}
+
+impl DerefMut for Entry {
+    fn deref_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+}
+
+/// What a link entry points to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Link {
+    /// A symbolic link, relative to the directory containing the entry.
+    Symbolic(String),
+    /// A hard link to the entry with the given path.
+    Hard(String),
+}
+
+impl Link {
+    pub fn target(&self) -> &str {
+        match self {
+            Link::Symbolic(target) | Link::Hard(target) => target,
+        }
+    }
+}
+
+/// What [`unpack`](crate::unpack) does with link entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkPolicy {
+    /// Creates the links. Symbolic links must not point outside of the
+    /// target directory.
+    Create,
+    /// Writes a copy of what the link points to, which has to be inside the
+    /// archive. A directory is copied at most once into each link.
+    Copy,
+    /// Fails if the archive contains links.
+    Reject,
+}
+
+/// Which attributes [`unpack`](crate::unpack) restores.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AttributePolicy {
+    /// Only restores modification times.
+    Timestamps,
+    /// Also restores permissions, except for the setuid and setgid bits.
+    #[default]
+    Permissions,
+    /// Restores everything, including ownership where the process is
+    /// permitted to change it and the setuid and setgid bits.
+    All,
+}
+
+/// Filesystem attributes of an entry, as captured by [`pack`](crate::pack)
+/// and restored by [`unpack`](crate::unpack).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Attributes {
+    /// Modification time in seconds since the Unix epoch.
+    pub mtime: i64,
+    /// Unix permission bits, including setuid, setgid and sticky.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
 pub struct FileWithSource<'a>(pub &'a File, pub &'a mut dyn Readable<'a>);
+
+/// Like [`FileWithSource`], for entries using features of version 3.
+pub struct EntryWithSource<'a>(pub &'a Entry, pub &'a mut dyn Readable<'a>);
+
+/// An archive to be merged by [`merge`](crate::merge), with its password if
+/// it is encrypted.
+pub struct ArchiveWithPassword<'a>(pub &'a mut dyn Readable<'a>, pub Option<&'a str>);
+
+/// What [`merge`](crate::merge) does if several archives contain the same path.
+///
+/// Directories present in several archives are merged regardless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keeps the entry of the archive that comes first.
+    FirstWins,
+    /// Keeps the entry of the archive that comes last, at the position of the
+    /// first one.
+    LastWins,
+    /// Fails with [`ErrorKind::AlreadyExists`](std::io::ErrorKind::AlreadyExists).
+    Error,
+}
+
+/// Optional features stored in the extension section of a v3 archive.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Extensions {
+    pub kdf: Kdf,
+    /// Stores an HMAC-SHA256 over the header, the ciphertext and the extension
+    /// section, which is checked before the body is decrypted. Requires
+    /// encryption.
+    pub authenticated: bool,
+    /// Stores a CRC32 per entry so entries can be verified individually.
+    /// Encrypted archives keep them in the encrypted body.
+    pub entry_checksums: bool,
+    /// The compression used for entries that do not set their own. This is
+    /// not stored, so it is always [`Compression::None`] when reading.
+    pub compression: Compression,
+    /// Compresses the whole body as one stream before it is encrypted, which
+    /// suits many small entries. Reading any entry then requires decompressing
+    /// the whole body.
+    pub body_compression: Compression,
+    /// Stores the data of entries with identical contents only once, later
+    /// entries reference the data of the first one.
+    pub deduplicate: bool,
+    /// A comment on the archive. Like the properties, it is not encrypted, but
+    /// covered by the MAC of authenticated archives.
+    pub comment: Option<String>,
+    /// Arbitrary key/value pairs, such as a build ID.
+    pub properties: BTreeMap<String, String>,
+}
+
+/// How the data of an entry is compressed.
+///
+/// Compressed entries are recorded in the extension section, so they require
+/// version 3.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Deflate,
+    Zstd,
+    /// LZMA in the xz container.
+    Lzma,
+}
+
+/// How the encryption key is derived from the password.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    /// Unsalted `sha256(password)`, as used by legacy archives.
+    #[default]
+    Sha256,
+    Pbkdf2 {
+        iterations: u32,
+        salt: [u8; 16],
+    },
+    Argon2 {
+        memory: u32,
+        iterations: u32,
+        parallelism: u32,
+        salt: [u8; 16],
+    },
+}
+
+/// The result of [`verify_detailed`](crate::verify_detailed).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// Whether the archive checksum matches, as reported by
+    /// [`verify_integrity`](crate::verify_integrity).
+    pub checksum: bool,
+    /// The state of every entry, in the same order as [`Metadata::files`].
+    pub entries: Vec<EntryIntegrity>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryIntegrity {
+    /// The data matches the entry's checksum and the padding is intact.
+    Valid,
+    /// The padding is intact, but there is no checksum to verify the data with.
+    Unverified,
+    /// The given byte range of the body is damaged.
+    Corrupted { offset: u64, length: u64 },
+}
+
+/// Entries salvaged from a damaged archive by [`recover`](crate::recover).
+#[derive(Debug)]
+pub struct Recovery {
+    pub version: u8,
+    pub files: Vec<Entry>,
+    /// Only kept if no entries were lost before the main file, as its index
+    /// cannot be trusted otherwise.
+    pub main_file: Option<u32>,
+    /// The `(offset, length)` ranges of the body no entry could be read from.
+    pub damaged: Vec<(u64, u64)>,
+    /// How many attributes and links of the extension section could not be
+    /// restored, as they belong to entries after a damaged range.
+    pub dropped: usize,
+}
+
+/// A tar entry skipped by [`from_tar`](crate::from_tar) because HSSP cannot
+/// represent it.
+#[cfg(feature = "tar")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedEntry {
+    pub path: String,
+    pub kind: SkippedKind,
+}
+
+#[cfg(feature = "tar")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkippedKind {
+    Symlink,
+    HardLink,
+    CharacterDevice,
+    BlockDevice,
+    Fifo,
+    /// Any other entry type, with its type flag.
+    Other(u8),
+}
+
+/// Sanity limits applied while parsing entries from untrusted archives.
+///
+/// Entries are always checked against the bytes actually available, these
+/// limits only tighten that further.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_entries: u32,
+    pub max_path_length: u16,
+    pub max_total_size: u64,
+    /// The largest length of a compressed body after decompressing it, as
+    /// the whole body is held in memory.
+    pub max_body_length: u64,
+    /// The most PBKDF2 iterations a password is derived with.
+    pub max_pbkdf2_iterations: u32,
+    /// The most memory Argon2 may use, in KiB.
+    pub max_argon2_memory: u32,
+    pub max_argon2_iterations: u32,
+    pub max_argon2_parallelism: u32,
+    /// Rejects encrypted archives without a MAC, so it cannot be stripped
+    /// from an authenticated archive unnoticed.
+    pub require_authentication: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        // Key derivation costs and decompressed bodies are bounded by
+        // default, as they are paid before anything else can be checked.
+        Self {
+            max_entries: u32::MAX,
+            max_path_length: u16::MAX,
+            max_total_size: u64::MAX,
+            max_body_length: 1 << 30,
+            max_pbkdf2_iterations: 10_000_000,
+            max_argon2_memory: 1 << 20,
+            max_argon2_iterations: 64,
+            max_argon2_parallelism: 16,
+            require_authentication: false,
+        }
+    }
+}
+
+/// An archive unpacked by [`prepare_run`](crate::prepare_run) to run its main
+/// file.
+#[derive(Debug)]
+pub struct Launch {
+    /// Where the archive was unpacked, which is the working directory of the
+    /// main file.
+    pub directory: PathBuf,
+    /// The unpacked main file inside `directory`.
+    pub main_file: PathBuf,
+    /// Whether `directory` was created for this launch and is removed by
+    /// [`Launch::cleanup`].
+    pub temporary: bool,
+}
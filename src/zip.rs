@@ -0,0 +1,298 @@
+use crate::{
+    checksum, create, entry_reader,
+    extension::{self, Section},
+    Attributes, Entry, File, Link, Metadata,
+};
+use ::zip::{
+    extra_fields::ExtraField, write::SimpleFileOptions, CompressionMethod, DateTime, ZipArchive,
+    ZipWriter,
+};
+use dh::{recommended::*, Readable, Rw};
+use std::io::{copy, BufReader, Error, ErrorKind, Read, Result, Seek, Write};
+
+// ZIP has no notion of a main file, so it is named in the archive comment.
+const MAIN_FILE_PREFIX: &str = "hssp-main-file:";
+
+/// Converts a ZIP archive into an unencrypted HSSP archive.
+///
+/// ZIP directory entries become HSSP directories. For version 3, symbolic
+/// links become links and the permissions and modification time of every
+/// entry are kept as its attributes. Entries are decompressed
+/// straight into `target` one at a time. If the archive comment has a line
+/// `hssp-main-file:<path>`, as written by [`to_zip`], that file becomes the
+/// main file. Like [`create`](crate::create), this returns the checksum to be
+/// written with [`write_hash`](crate::write_hash).
+pub fn from_zip(reader: &mut dyn Readable, version: u8, target: &mut dyn Rw) -> Result<(u64, u32)> {
+    let mut zip = ZipArchive::new(reader)?;
+
+    let mut files = Vec::new();
+    let mut links = Vec::new();
+    for i in 0..zip.len() {
+        let entry = zip.by_index_raw(i)?;
+        let symlink = entry.is_symlink();
+        if symlink && version < 3 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Symbolic link {:?} requires HSSP version 3", entry.name()),
+            ));
+        }
+        let directory = entry.is_dir();
+        let mut file = Entry {
+            attributes: (version >= 3).then(|| capture(&entry)),
+            ..Entry::from(File {
+                path: entry.name().trim_end_matches('/').to_string(),
+                directory,
+                length: if directory { 0 } else { entry.size() },
+                ..Default::default()
+            })
+        };
+        drop(entry);
+        // The data of a symbolic link is its target.
+        if symlink {
+            let mut target = String::new();
+            zip.by_index(i)?.read_to_string(&mut target)?;
+            file.link = Some(Link::Symbolic(target));
+            links.push((i as u32, false));
+        }
+        create::validate(&file)?;
+        files.push(file);
+    }
+
+    let main_file = match String::from_utf8_lossy(zip.comment())
+        .lines()
+        .find_map(|line| line.strip_prefix(MAIN_FILE_PREFIX))
+    {
+        Some(path) => {
+            let index = files
+                .iter()
+                .position(|file| !file.directory && file.path == path)
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Main file {:?} is not in the archive", path),
+                    )
+                })? as u32;
+            // Links cannot be the main file either.
+            create::validate_main_file(
+                index,
+                files.get(index as usize),
+                files.len(),
+                ErrorKind::InvalidInput,
+            )?;
+            Some(index)
+        }
+        None => None,
+    };
+
+    let hash_pos = create::write_header(
+        target,
+        version,
+        files.len() as u32,
+        &[0; 32],
+        &[0; 16],
+        main_file,
+    )?;
+    let body_pos = target.pos()?;
+
+    for (i, file) in files.iter().enumerate() {
+        let padding = create::write_entry_header(target, file)?;
+        if !file.directory {
+            let mut entry = zip.by_index(i)?;
+            if copy(&mut entry, target)? != file.length {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Size of {:?} does not match", file.path),
+                ));
+            }
+        }
+        target.write_bytes(&vec![0; padding])?;
+    }
+
+    if version >= 3 && !files.is_empty() {
+        extension::write(
+            target,
+            &Section {
+                attributes: Some(files.iter().map(|file| file.attributes).collect()),
+                links: (!links.is_empty()).then_some(links),
+                ..Default::default()
+            },
+        )?;
+    }
+
+    let body_size = target.pos()? - body_pos;
+    let hash = checksum::compute(Readable::as_trait(target), body_pos, body_size)?;
+
+    Ok((hash_pos, hash))
+}
+
+/// Converts an HSSP archive into a ZIP archive with deflated entries.
+///
+/// `source` has to be the archive `meta` was read from. Encrypted archives
+/// are read from [`Encryption::decrypted`](crate::Encryption::decrypted), so
+/// `meta` has to be read with the right password, and archives with a
+/// compressed body from [`Metadata::decompressed`]. The main file is named in
+/// the archive comment, see [`from_zip`]. Attributes are kept as far as ZIP
+/// can store them, and as ZIP has no hard links, they become copies of the
+/// file they point to.
+pub fn to_zip(
+    source: &mut dyn Readable,
+    meta: &Metadata,
+    target: &mut dyn Rw,
+    buffer_size: u64,
+) -> Result<()> {
+    let mut zip = ZipWriter::new(target);
+    match &meta.encryption {
+        Some(encryption) if encryption.hash != encryption.hash_expected => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Archive has not been decrypted",
+            ))
+        }
+        Some(encryption) => write_files(
+            &mut zip,
+            &mut dh::data::read_ref(&encryption.decrypted),
+            &meta.files,
+            buffer_size,
+        )?,
+        None => match &meta.decompressed {
+            Some(body) => write_files(
+                &mut zip,
+                &mut dh::data::read_ref(body),
+                &meta.files,
+                buffer_size,
+            )?,
+            None => write_files(&mut zip, source, &meta.files, buffer_size)?,
+        },
+    }
+
+    if let Some(main_file) = meta.main_file.and_then(|i| meta.files.get(i as usize)) {
+        zip.set_comment(format!("{}{}", MAIN_FILE_PREFIX, main_file.path))?;
+    }
+    zip.finish()?;
+
+    Ok(())
+}
+
+fn write_files<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    body: &mut dyn Readable,
+    files: &[Entry],
+    buffer_size: u64,
+) -> Result<()> {
+    let default_options =
+        SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for file in files {
+        let mut options = default_options;
+        if let Some(attributes) = &file.attributes {
+            options = options
+                .unix_permissions(attributes.mode)
+                .last_modified_time(to_date_time(attributes.mtime));
+        }
+
+        if let Some(Link::Symbolic(target)) = &file.link {
+            zip.add_symlink(file.path.as_str(), target, options)?;
+            continue;
+        }
+        if file.directory {
+            zip.add_directory(file.path.as_str(), options)?;
+            continue;
+        }
+
+        let data = match &file.link {
+            Some(Link::Hard(target)) => files
+                .iter()
+                .find(|other| other.path == *target && !other.directory && other.link.is_none())
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Hard link {:?} points to a missing file", file.path),
+                    )
+                })?,
+            _ => file,
+        };
+        zip.start_file(
+            file.path.as_str(),
+            options.large_file(data.uncompressed_length >= u32::MAX as u64),
+        )?;
+        let mut data = BufReader::with_capacity(buffer_size as usize, entry_reader(body, data)?);
+        copy(&mut data, zip)?;
+    }
+
+    Ok(())
+}
+
+fn capture<R: Read>(entry: &::zip::read::ZipFile<R>) -> Attributes {
+    // The extended timestamp is in UTC, the DOS one is taken to be.
+    let mtime = entry
+        .extra_data_fields()
+        .find_map(|field| match field {
+            ExtraField::ExtendedTimestamp(timestamp) => timestamp.mod_time(),
+            _ => None,
+        })
+        .map(i64::from)
+        .or_else(|| entry.last_modified().map(from_date_time))
+        .unwrap_or_default();
+    let mode = match entry.unix_mode() {
+        Some(mode) => mode & 0o7777,
+        None if entry.is_dir() => 0o755,
+        None => 0o644,
+    };
+    Attributes {
+        mtime,
+        mode,
+        ..Default::default()
+    }
+}
+
+fn from_date_time(time: DateTime) -> i64 {
+    let days = days_from_civil(time.year() as i64, time.month() as i64, time.day() as i64);
+    days * 86400 + time.hour() as i64 * 3600 + time.minute() as i64 * 60 + time.second() as i64
+}
+
+/// Converts seconds since the Unix epoch to a DOS timestamp, which is clamped
+/// to the years it can represent.
+fn to_date_time(mtime: i64) -> DateTime {
+    let min = from_date_time(DateTime::default());
+    let max = days_from_civil(2108, 1, 1) * 86400 - 1;
+    let mtime = mtime.clamp(min, max);
+    let (year, month, day) = civil_from_days(mtime.div_euclid(86400));
+    let seconds = mtime.rem_euclid(86400);
+    DateTime::from_date_and_time(
+        year as u16,
+        month as u8,
+        day as u8,
+        (seconds / 3600) as u8,
+        (seconds / 60 % 60) as u8,
+        (seconds % 60) as u8,
+    )
+    .unwrap_or_default()
+}
+
+// Conversions between days since the Unix epoch and dates of the proleptic
+// Gregorian calendar, from https://howardhinnant.github.io/date_algorithms.html.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,192 @@
+mod common;
+
+use common::read;
+use hssp2::{
+    append, create_extended, metadata, rehash, verify_detailed, verify_integrity, write_hash,
+    Entry, EntryIntegrity, EntryWithSource, Extensions, File,
+};
+use std::io::ErrorKind;
+
+fn append_to(archive: Vec<u8>, entries: &[(&str, &[u8])]) -> std::io::Result<Vec<u8>> {
+    let files: Vec<Entry> = entries
+        .iter()
+        .map(|(path, data)| {
+            Entry::from(File {
+                path: path.to_string(),
+                length: data.len() as u64,
+                ..Default::default()
+            })
+        })
+        .collect();
+    let mut readers: Vec<_> = entries
+        .iter()
+        .map(|(_, data)| dh::data::read_ref(data))
+        .collect();
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| EntryWithSource(file, reader))
+        .collect();
+
+    let mut target = dh::data::rw(archive);
+    append(&mut target, sources, 1024)?;
+    Ok(dh::data::close(target))
+}
+
+#[test]
+fn append_every_version() {
+    for (path, version) in [
+        ("tests/samples/wfld-multiple.hssp", 1),
+        ("tests/samples/rfld-multiple.hssp", 2),
+        ("tests/samples/dhdr-multiple.hssp", 3),
+    ] {
+        let archive = std::fs::read(path).unwrap();
+        let archive = append_to(archive, &[("new.txt", b"Appended"), ("new2.txt", b"")]).unwrap();
+
+        let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+        assert_eq!(meta.version, version, "{}", path);
+        assert!(verify_integrity(&mut dh::data::read_ref(&archive), &meta).unwrap());
+        assert_eq!(meta.files.len(), 4);
+        assert_eq!(meta.files[0].path, "test.txt");
+        assert_eq!(meta.files[2].path, "new.txt");
+        assert_eq!(meta.files[3].path, "new2.txt");
+        assert_eq!(read(&archive, &meta.files[0]), b"Hello, world!");
+        assert_eq!(read(&archive, &meta.files[2]), b"Appended");
+        assert_eq!(read(&archive, &meta.files[3]), b"");
+    }
+}
+
+#[test]
+fn append_keeps_main_file() {
+    let archive = std::fs::read("tests/samples/dhdr-withmain.hssp").unwrap();
+    let archive = append_to(archive, &[("new.txt", b"Appended")]).unwrap();
+
+    let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+    assert_eq!(meta.main_file, Some(0));
+    assert_eq!(meta.files.len(), 2);
+}
+
+#[test]
+fn append_entry_checksums() {
+    let mut target = dh::data::rw_empty();
+    let mut reader = dh::data::read_ref(b"Hello, world!");
+    let result = create_extended(
+        3,
+        vec![EntryWithSource(
+            &File {
+                path: "test.txt".to_string(),
+                length: 13,
+                ..Default::default()
+            }
+            .into(),
+            &mut reader,
+        )],
+        None,
+        None,
+        Some(&Extensions {
+            entry_checksums: true,
+            ..Default::default()
+        }),
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+    let archive = append_to(dh::data::close(target), &[("new.txt", b"Appended")]).unwrap();
+
+    let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+    assert_eq!(meta.files.len(), 2);
+    assert!(meta.files.iter().all(|file| file.checksum.is_some()));
+    let report = verify_detailed(&mut dh::data::read_ref(&archive), &meta).unwrap();
+    assert!(report.checksum);
+    assert_eq!(report.entries, vec![EntryIntegrity::Valid; 2]);
+    assert_eq!(read(&archive, &meta.files[1]), b"Appended");
+}
+
+#[test]
+fn append_unknown_record() {
+    let mut target = dh::data::rw_empty();
+    let mut reader = dh::data::read_ref(b"Hello, world!");
+    let result = create_extended(
+        3,
+        vec![EntryWithSource(
+            &File {
+                path: "test.txt".to_string(),
+                length: 13,
+                ..Default::default()
+            }
+            .into(),
+            &mut reader,
+        )],
+        None,
+        None,
+        Some(&Extensions {
+            comment: Some("Current".to_string()),
+            ..Default::default()
+        }),
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+    let mut archive = dh::data::close(target);
+
+    // A non-critical record this reader does not know, and a comment that is
+    // superseded by the one already in the section, so the rewritten section
+    // is shorter than the old one.
+    let mut unknown = vec![0x00, 0x01, 200, 0, 0, 0];
+    unknown.extend([0xab; 200]);
+    let mut comment = vec![9, 0, 250, 0, 0, 0];
+    comment.extend([b'x'; 250]);
+    let offset = u64::from_le_bytes(archive[100..108].try_into().unwrap()) as usize;
+    let section = archive.split_off(offset);
+    archive.extend(&comment);
+    archive.extend(&section);
+    archive.extend(&unknown);
+    let length = (comment.len() + section.len() + unknown.len()) as u32;
+    archive[108..112].copy_from_slice(&length.to_le_bytes());
+    let mut target = dh::data::rw(archive);
+    rehash(&mut target, false).unwrap();
+
+    let archive = append_to(dh::data::close(target), &[("new.txt", b"Appended")]).unwrap();
+
+    let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+    assert!(verify_integrity(&mut dh::data::read_ref(&archive), &meta).unwrap());
+    assert_eq!(meta.files.len(), 2);
+    assert_eq!(meta.comment(), Some("Current"));
+    assert_eq!(read(&archive, &meta.files[1]), b"Appended");
+    assert!(archive.ends_with(&unknown));
+    let offset = u64::from_le_bytes(archive[100..108].try_into().unwrap());
+    let length = u32::from_le_bytes(archive[108..112].try_into().unwrap());
+    assert_eq!(offset + length as u64, archive.len() as u64);
+}
+
+#[test]
+fn append_untruncatable() {
+    let mut archive = std::fs::read("tests/samples/rfld-normal.hssp").unwrap();
+    archive.resize(archive.len() + 1024, 0);
+    let mut target = dh::data::rw_ref(&mut archive);
+    let error = append(&mut target, Vec::new(), 1024).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn append_encrypted() {
+    let archive = std::fs::read("tests/samples/rfld-encrypted.hssp").unwrap();
+    let error = append_to(archive, &[("new.txt", b"Appended")]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn append_corrupted() {
+    let archive = std::fs::read("tests/samples/rfld-corrupted.hssp").unwrap();
+    let error = append_to(archive, &[("new.txt", b"Appended")]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn append_invalid_path() {
+    let archive = std::fs::read("tests/samples/rfld-normal.hssp").unwrap();
+    let error = append_to(archive, &[("//new.txt", b"Appended")]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
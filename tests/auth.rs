@@ -0,0 +1,133 @@
+use acr::hash::murmur3;
+use dh::recommended::*;
+use hssp2::{
+    create_extended, metadata, metadata_with_limits, verify_integrity, write_hash, ArchiveError,
+    EntryWithSource, Extensions, File, Limits,
+};
+
+fn create_authenticated(encryption: Option<(&str, &[u8; 16])>) -> std::io::Result<Vec<u8>> {
+    let mut target = dh::data::rw_empty();
+    let mut test_txt = dh::data::read_ref(b"Hello, world!");
+
+    let result = create_extended(
+        3,
+        vec![EntryWithSource(
+            &File {
+                path: "test.txt".to_string(),
+                directory: false,
+                offset: 0,
+                length: 13,
+            }
+            .into(),
+            &mut test_txt,
+        )],
+        encryption,
+        None,
+        Some(&Extensions {
+            authenticated: true,
+            comment: Some("Release".to_string()),
+            ..Default::default()
+        }),
+        &mut target,
+        1024,
+    )?;
+
+    write_hash(&mut target, result)?;
+    Ok(dh::data::close(target))
+}
+
+// Simulates an attacker who also fixes up the checksum after tampering.
+fn rehash(archive: &mut [u8]) {
+    let size = archive.len() as u64;
+    let hash = murmur3(
+        &mut dh::data::read_ref(archive),
+        128,
+        size - 128,
+        0x31082007,
+    )
+    .unwrap();
+    archive[4..8].copy_from_slice(&hash.to_le_bytes());
+}
+
+#[test]
+fn auth_valid() {
+    let archive = create_authenticated(Some(("Password", &[1; 16]))).unwrap();
+
+    let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+    assert!(meta.extensions.unwrap().authenticated);
+
+    let meta = metadata(&mut dh::data::read_ref(&archive), Some("Password")).unwrap();
+    assert!(verify_integrity(&mut dh::data::read_ref(&archive), &meta).unwrap());
+    assert!(meta.extensions.unwrap().authenticated);
+    assert_eq!(meta.files.len(), 1);
+    assert_eq!(meta.files[0].path, "test.txt");
+}
+
+#[test]
+fn auth_tampered_ciphertext() {
+    let mut archive = create_authenticated(Some(("Password", &[1; 16]))).unwrap();
+    archive[130] ^= 1;
+    rehash(&mut archive);
+
+    let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+    assert!(verify_integrity(&mut dh::data::read_ref(&archive), &meta).unwrap());
+
+    let err = metadata(&mut dh::data::read_ref(&archive), Some("Password")).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn auth_tampered_header() {
+    let mut archive = create_authenticated(Some(("Password", &[1; 16]))).unwrap();
+    archive[60] = 1;
+
+    assert!(metadata(&mut dh::data::read_ref(&archive), Some("Password")).is_err());
+}
+
+#[test]
+fn auth_requires_encryption() {
+    assert!(create_authenticated(None).is_err());
+}
+
+fn archive_error(error: std::io::Error) -> ArchiveError {
+    *error
+        .get_ref()
+        .unwrap()
+        .downcast_ref::<ArchiveError>()
+        .unwrap()
+}
+
+#[test]
+fn auth_tampered_section() {
+    let mut archive = create_authenticated(Some(("Password", &[1; 16]))).unwrap();
+    let comment = archive.windows(7).position(|w| w == b"Release").unwrap();
+    archive[comment..comment + 7].copy_from_slice(b"Rogue!!");
+    rehash(&mut archive);
+
+    let err = metadata(&mut dh::data::read_ref(&archive), Some("Password")).unwrap_err();
+    assert_eq!(archive_error(err), ArchiveError::AuthenticationFailed);
+}
+
+#[test]
+fn auth_stripped_mac() {
+    let mut archive = create_authenticated(Some(("Password", &[1; 16]))).unwrap();
+    let section = u64::from_le_bytes(archive[100..108].try_into().unwrap());
+    archive.truncate(section as usize);
+    archive[96..112].fill(0);
+    archive[130] ^= 1;
+    rehash(&mut archive);
+
+    let limits = Limits {
+        require_authentication: true,
+        ..Default::default()
+    };
+    let err = metadata_with_limits(&mut dh::data::read_ref(&archive), Some("Password"), &limits)
+        .unwrap_err();
+    assert_eq!(archive_error(err), ArchiveError::Unauthenticated);
+
+    // Authenticated archives are still accepted.
+    let archive = create_authenticated(Some(("Password", &[1; 16]))).unwrap();
+    assert!(
+        metadata_with_limits(&mut dh::data::read_ref(&archive), Some("Password"), &limits).is_ok()
+    );
+}
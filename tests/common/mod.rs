@@ -0,0 +1,27 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use hssp2::{extract, metadata, verify_integrity, Entry, Metadata};
+
+/// Reads the metadata of an unencrypted archive and checks its integrity.
+pub fn read_meta(archive: &[u8]) -> Metadata {
+    read_meta_with(archive, None)
+}
+
+/// Reads the metadata of an archive and checks its integrity.
+pub fn read_meta_with(archive: &[u8], password: Option<&str>) -> Metadata {
+    let meta = metadata(&mut dh::data::read_ref(archive), password).unwrap();
+    assert!(verify_integrity(&mut dh::data::read_ref(archive), &meta).unwrap());
+    meta
+}
+
+/// Extracts an entry of an archive, or of a decrypted body.
+pub fn read(archive: &[u8], file: &Entry) -> Vec<u8> {
+    try_read(archive, file).unwrap()
+}
+
+pub fn try_read(archive: &[u8], file: &Entry) -> std::io::Result<Vec<u8>> {
+    let mut target = dh::data::write_new(file.uncompressed_length);
+    extract(&mut dh::data::read_ref(archive), file, &mut target, 1024, 0)?;
+    Ok(dh::data::close(target))
+}
@@ -0,0 +1,408 @@
+#![cfg(all(feature = "deflate", feature = "zstd", feature = "lzma"))]
+
+mod common;
+
+use common::{read_meta, try_read};
+use hssp2::{
+    append, convert, create_extended, entry_reader, metadata_with_limits, recover, remove,
+    verify_detailed, write_hash, ArchiveError, Compression, Entry, EntryIntegrity, EntryWithSource,
+    Extensions, File, Limits,
+};
+use std::io::{ErrorKind, Read};
+
+const TEXT: &[u8] = b"The quick brown fox jumps over the lazy dog. ";
+
+fn text(repeat: usize) -> Vec<u8> {
+    TEXT.repeat(repeat)
+}
+
+fn create_compressed(
+    version: u8,
+    entries: &[(&str, &[u8], Compression)],
+    extensions: Option<&Extensions>,
+) -> std::io::Result<Vec<u8>> {
+    let files: Vec<Entry> = entries
+        .iter()
+        .map(|(path, data, compression)| Entry {
+            compression: *compression,
+            ..Entry::from(File {
+                path: path.to_string(),
+                length: data.len() as u64,
+                ..Default::default()
+            })
+        })
+        .collect();
+    let mut readers: Vec<_> = entries
+        .iter()
+        .map(|(_, data, _)| dh::data::read_ref(data))
+        .collect();
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| EntryWithSource(file, reader))
+        .collect();
+
+    let mut target = dh::data::rw_empty();
+    let result = create_extended(version, sources, None, None, extensions, &mut target, 1024)?;
+    write_hash(&mut target, result)?;
+    Ok(dh::data::close(target))
+}
+
+#[test]
+fn compression_algorithms() {
+    let data = text(1000);
+    for compression in [Compression::Deflate, Compression::Zstd, Compression::Lzma] {
+        let archive = create_compressed(
+            3,
+            &[("text.txt", &data, Compression::None)],
+            Some(&Extensions {
+                compression,
+                entry_checksums: true,
+                ..Default::default()
+            }),
+        )
+        .unwrap();
+
+        let meta = read_meta(&archive);
+        let file = &meta.files[0];
+        assert_eq!(file.compression, compression);
+        assert_eq!(file.uncompressed_length, data.len() as u64);
+        assert!(file.length < data.len() as u64 / 10, "{:?}", compression);
+        assert_eq!(try_read(&archive, file).unwrap(), data);
+
+        let mut decompressed = Vec::new();
+        entry_reader(&mut dh::data::read_ref(&archive), file)
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+
+        let report = verify_detailed(&mut dh::data::read_ref(&archive), &meta).unwrap();
+        assert_eq!(report.entries, vec![EntryIntegrity::Valid]);
+    }
+}
+
+#[test]
+fn compression_per_entry() {
+    let data = text(100);
+    let archive = create_compressed(
+        3,
+        &[
+            ("stored.txt", &data, Compression::None),
+            ("zstd.txt", &data, Compression::Zstd),
+            ("empty.txt", b"", Compression::Deflate),
+        ],
+        None,
+    )
+    .unwrap();
+
+    let meta = read_meta(&archive);
+    let compression: Vec<_> = meta.files.iter().map(|f| f.compression).collect();
+    assert_eq!(
+        compression,
+        [Compression::None, Compression::Zstd, Compression::Deflate]
+    );
+    assert_eq!(meta.files[0].length, data.len() as u64);
+    assert_eq!(meta.files[0].uncompressed_length, data.len() as u64);
+    for (file, expected) in meta.files.iter().zip([&data[..], &data, b""]) {
+        assert_eq!(try_read(&archive, file).unwrap(), expected);
+    }
+}
+
+#[test]
+fn compression_requires_v3() {
+    let error = create_compressed(2, &[("a.txt", b"a", Compression::Deflate)], None).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+    let archive = create_compressed(3, &[("a.txt", b"a", Compression::Deflate)], None).unwrap();
+    let mut target = dh::data::rw_empty();
+    let error = convert(
+        &mut dh::data::read_ref(&archive),
+        None,
+        2,
+        &mut target,
+        1024,
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn compression_wrong_length() {
+    let data = text(10);
+    let archive = create_compressed(3, &[("a.txt", &data, Compression::Deflate)], None).unwrap();
+    let mut meta = read_meta(&archive);
+
+    for uncompressed_length in [data.len() as u64 - 1, data.len() as u64 + 1] {
+        meta.files[0].uncompressed_length = uncompressed_length;
+        let error = try_read(&archive, &meta.files[0]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn compression_append() {
+    let archive = std::fs::read("tests/samples/dhdr-multiple.hssp").unwrap();
+    let data = text(100);
+    let file = Entry {
+        compression: Compression::Lzma,
+        ..Entry::from(File {
+            path: "new.txt".to_string(),
+            length: data.len() as u64,
+            ..Default::default()
+        })
+    };
+    let mut target = dh::data::rw(archive);
+    append(
+        &mut target,
+        vec![EntryWithSource(&file, &mut dh::data::read_ref(&data))],
+        1024,
+    )
+    .unwrap();
+    let archive = dh::data::close(target);
+
+    let meta = read_meta(&archive);
+    assert_eq!(meta.files.len(), 3);
+    assert_eq!(meta.files[0].compression, Compression::None);
+    assert_eq!(
+        try_read(&archive, &meta.files[0]).unwrap(),
+        b"Hello, world!"
+    );
+    assert_eq!(meta.files[2].compression, Compression::Lzma);
+    assert_eq!(try_read(&archive, &meta.files[2]).unwrap(), data);
+}
+
+#[test]
+fn compression_remove() {
+    let data = text(100);
+    let archive = create_compressed(
+        3,
+        &[
+            ("a.txt", b"a", Compression::None),
+            ("b.txt", &data, Compression::Zstd),
+        ],
+        None,
+    )
+    .unwrap();
+    let meta = read_meta(&archive);
+
+    let mut target = dh::data::rw_empty();
+    let result = remove(
+        &mut dh::data::read_ref(&archive),
+        &meta,
+        &["a.txt"],
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+    let removed = dh::data::close(target);
+
+    let meta_removed = read_meta(&removed);
+    assert_eq!(meta_removed.files[0].compression, Compression::Zstd);
+    // The compressed data is copied as is.
+    assert_eq!(meta_removed.files[0].length, meta.files[1].length);
+    assert_eq!(try_read(&removed, &meta_removed.files[0]).unwrap(), data);
+}
+
+fn create_body_compressed(compression: Compression) -> (Vec<u8>, Vec<String>) {
+    let paths: Vec<String> = (0..200).map(|i| format!("file-{}.txt", i)).collect();
+    let entries: Vec<_> = paths
+        .iter()
+        .map(|path| (path.as_str(), TEXT, Compression::None))
+        .collect();
+    let archive = create_compressed(
+        3,
+        &entries,
+        Some(&Extensions {
+            body_compression: compression,
+            entry_checksums: true,
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+    (archive, paths)
+}
+
+#[test]
+fn body_compression() {
+    let (stored, _) = create_body_compressed(Compression::None);
+    for compression in [Compression::Deflate, Compression::Zstd, Compression::Lzma] {
+        let (archive, paths) = create_body_compressed(compression);
+        assert!(archive.len() < stored.len() / 4, "{:?}", compression);
+
+        let meta = read_meta(&archive);
+        assert_eq!(
+            meta.extensions.as_ref().unwrap().body_compression,
+            compression
+        );
+        let body = meta.decompressed.as_ref().unwrap();
+        assert_eq!(meta.files.len(), paths.len());
+        for (file, path) in meta.files.iter().zip(&paths) {
+            assert_eq!(&file.path, path);
+            assert_eq!(file.compression, Compression::None);
+            assert_eq!(try_read(body, file).unwrap(), TEXT);
+        }
+
+        let report = verify_detailed(&mut dh::data::read_ref(&archive), &meta).unwrap();
+        assert!(report.entries.iter().all(|e| *e == EntryIntegrity::Valid));
+    }
+}
+
+#[test]
+fn body_compression_edit() {
+    let (archive, paths) = create_body_compressed(Compression::Zstd);
+    let meta = read_meta(&archive);
+
+    let mut target = dh::data::rw_empty();
+    let result = remove(
+        &mut dh::data::read_ref(&archive),
+        &meta,
+        &[paths[0].as_str()],
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+    let removed = dh::data::close(target);
+
+    let meta = read_meta(&removed);
+    assert_eq!(
+        meta.extensions.as_ref().unwrap().body_compression,
+        Compression::Zstd
+    );
+    assert_eq!(meta.files.len(), paths.len() - 1);
+    assert_eq!(meta.files[0].path, paths[1]);
+    assert_eq!(
+        try_read(meta.decompressed.as_ref().unwrap(), &meta.files[0]).unwrap(),
+        TEXT
+    );
+
+    let mut target = dh::data::rw(removed);
+    let file = Entry::from(File {
+        path: "new.txt".to_string(),
+        length: 1,
+        ..Default::default()
+    });
+    let error = append(
+        &mut target,
+        vec![EntryWithSource(&file, &mut dh::data::read_ref(b"a"))],
+        1024,
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn body_compression_limits() {
+    let (archive, _) = create_body_compressed(Compression::Deflate);
+    let limits = Limits {
+        max_total_size: TEXT.len() as u64,
+        ..Default::default()
+    };
+    let error = metadata_with_limits(&mut dh::data::read_ref(&archive), None, &limits).unwrap_err();
+    assert!(matches!(
+        error.get_ref().and_then(|e| e.downcast_ref()),
+        Some(ArchiveError::TooLarge)
+    ));
+
+    // The declared length is all that bounds the decompressed body, so it
+    // is limited by default.
+    let mut declared = archive.clone();
+    let mut pos = u64::from_le_bytes(declared[100..108].try_into().unwrap()) as usize;
+    while u16::from_le_bytes([declared[pos], declared[pos + 1]]) != 0x8005 {
+        pos += 6 + u32::from_le_bytes(declared[pos + 2..pos + 6].try_into().unwrap()) as usize;
+    }
+    declared[pos + 7..pos + 15].copy_from_slice(&(1u64 << 40).to_le_bytes());
+    let error = metadata_with_limits(&mut dh::data::read_ref(&declared), None, &Limits::default())
+        .unwrap_err();
+    assert!(matches!(
+        error.get_ref().and_then(|e| e.downcast_ref()),
+        Some(ArchiveError::TooLarge)
+    ));
+
+    let mut target = dh::data::rw_empty();
+    let error = convert(
+        &mut dh::data::read_ref(&archive),
+        None,
+        2,
+        &mut target,
+        1024,
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn compression_recover() {
+    let data = text(100);
+    let archive = create_compressed(3, &[("a.txt", &data, Compression::Deflate)], None).unwrap();
+    let (body_compressed, _) = create_body_compressed(Compression::Deflate);
+
+    for archive in [archive, body_compressed] {
+        let error = recover(&mut dh::data::read_ref(&archive)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+}
+
+#[test]
+fn compression_legacy_readers() {
+    // Readers that predate the extension section only accept zeroed reserved
+    // header space as version 3, and otherwise parse an entry from there.
+    let legacy_fails = |archive: &[u8]| {
+        let reserved = &archive[64..96];
+        let path_length = u16::from_le_bytes([reserved[8], reserved[9]]) as usize;
+        reserved.iter().any(|&b| b != 0)
+            && std::str::from_utf8(&reserved[10..10 + path_length]).is_err()
+    };
+
+    let data = text(10);
+    let plain = create_compressed(
+        3,
+        &[("a.txt", &data, Compression::None)],
+        Some(&Extensions {
+            entry_checksums: true,
+            ..Default::default()
+        }),
+    )
+    .unwrap();
+    assert!(!legacy_fails(&plain));
+
+    let compressed = create_compressed(3, &[("a.txt", &data, Compression::Zstd)], None).unwrap();
+    let (body_compressed, _) = create_body_compressed(Compression::Zstd);
+    let converted = {
+        let mut target = dh::data::rw_empty();
+        let result = convert(
+            &mut dh::data::read_ref(&compressed),
+            None,
+            3,
+            &mut target,
+            1024,
+        )
+        .unwrap();
+        write_hash(&mut target, result).unwrap();
+        dh::data::close(target)
+    };
+    let appended = {
+        let mut target = dh::data::rw(plain);
+        let file = Entry {
+            compression: Compression::Lzma,
+            ..Entry::from(File {
+                path: "b.txt".to_string(),
+                length: data.len() as u64,
+                ..Default::default()
+            })
+        };
+        append(
+            &mut target,
+            vec![EntryWithSource(&file, &mut dh::data::read_ref(&data))],
+            1024,
+        )
+        .unwrap();
+        dh::data::close(target)
+    };
+    for archive in [compressed, body_compressed, converted, appended] {
+        assert!(legacy_fails(&archive));
+        assert_eq!(read_meta(&archive).version, 3);
+    }
+}
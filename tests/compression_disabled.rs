@@ -0,0 +1,45 @@
+#![cfg(not(all(feature = "deflate", feature = "zstd", feature = "lzma")))]
+
+use hssp2::{create_extended, entry_reader, Compression, Entry, EntryWithSource, File};
+use std::io::ErrorKind;
+
+const DISABLED: &[Compression] = &[
+    #[cfg(not(feature = "deflate"))]
+    Compression::Deflate,
+    #[cfg(not(feature = "zstd"))]
+    Compression::Zstd,
+    #[cfg(not(feature = "lzma"))]
+    Compression::Lzma,
+];
+
+#[test]
+fn compression_disabled() {
+    for &compression in DISABLED {
+        let file = Entry {
+            compression,
+            ..Entry::from(File {
+                path: "a.txt".to_string(),
+                length: 5,
+                ..Default::default()
+            })
+        };
+
+        let mut target = dh::data::rw_empty();
+        let error = create_extended(
+            3,
+            vec![EntryWithSource(&file, &mut dh::data::read_ref(b"Hello"))],
+            None,
+            None,
+            None,
+            &mut target,
+            1024,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+        let error = entry_reader(&mut dh::data::read_ref(b"Hello"), &file)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use common::{read, read_meta_with};
+use hssp2::{convert, create_extended, write_hash, EntryWithSource, Extensions, File, Kdf};
+use std::io::ErrorKind;
+
+fn convert_to(archive: &[u8], password: Option<&str>, version: u8) -> std::io::Result<Vec<u8>> {
+    let mut target = dh::data::rw_empty();
+    let result = convert(
+        &mut dh::data::read_ref(archive),
+        password,
+        version,
+        &mut target,
+        1024,
+    )?;
+    write_hash(&mut target, result)?;
+    Ok(dh::data::close(target))
+}
+
+fn create_v3(extensions: &Extensions, encrypted: bool) -> Vec<u8> {
+    let mut target = dh::data::rw_empty();
+    let mut reader = dh::data::read_ref(b"Hello, world!");
+    let result = create_extended(
+        3,
+        vec![EntryWithSource(
+            &File {
+                path: "test.txt".to_string(),
+                length: 13,
+                ..Default::default()
+            }
+            .into(),
+            &mut reader,
+        )],
+        encrypted.then_some(("Password", &[7; 16])),
+        Some(0),
+        Some(extensions),
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+    dh::data::close(target)
+}
+
+#[test]
+fn convert_between_versions() {
+    for path in [
+        "tests/samples/wfld-multiple.hssp",
+        "tests/samples/rfld-multiple.hssp",
+        "tests/samples/dhdr-multiple.hssp",
+    ] {
+        let original = std::fs::read(path).unwrap();
+        let original_size = original.len() - if path.contains("dhdr") { 128 } else { 64 };
+
+        for version in 1..=3 {
+            let archive = convert_to(&original, None, version).unwrap();
+            let header_size = if version == 3 { 128 } else { 64 };
+            assert_eq!(archive.len() - header_size, original_size);
+
+            let meta = read_meta_with(&archive, None);
+            assert_eq!(meta.version, version, "{}", path);
+            assert_eq!(meta.files.len(), 2);
+            assert_eq!(read(&archive, &meta.files[0]), b"Hello, world!");
+            assert_eq!(read(&archive, &meta.files[1]), b"Hello, world! 2");
+        }
+    }
+}
+
+#[test]
+fn convert_keeps_main_file() {
+    let original = std::fs::read("tests/samples/dhdr-withmain.hssp").unwrap();
+    let archive = convert_to(&original, None, 1).unwrap();
+
+    assert_eq!(read_meta_with(&archive, None).main_file, Some(0));
+}
+
+#[test]
+fn convert_encrypted() {
+    let original = std::fs::read("tests/samples/wfld-encrypted.hssp").unwrap();
+    let archive = convert_to(&original, None, 3).unwrap();
+
+    // The ciphertext is copied without decrypting it.
+    assert_eq!(archive[128..], original[64..]);
+    let meta = read_meta_with(&archive, Some("Password"));
+    assert_eq!(meta.version, 3);
+    let body = &meta.encryption.as_ref().unwrap().decrypted;
+    assert_eq!(read(body, &meta.files[0]), b"Hello, world!");
+}
+
+#[test]
+fn convert_extensions() {
+    let extensions = Extensions {
+        kdf: Kdf::Pbkdf2 {
+            iterations: 1,
+            salt: [3; 16],
+        },
+        authenticated: true,
+        entry_checksums: true,
+        ..Default::default()
+    };
+    let original = create_v3(&extensions, true);
+
+    let archive = convert_to(&original, None, 3).unwrap();
+    assert_eq!(archive, original);
+
+    let error = convert_to(&original, None, 2).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = convert_to(&original, Some("password"), 2).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+    let archive = convert_to(&original, Some("Password"), 2).unwrap();
+    let meta = read_meta_with(&archive, Some("Password"));
+    assert_eq!(meta.version, 2);
+    assert_eq!(meta.extensions, None);
+    assert_eq!(meta.main_file, Some(0));
+    let body = &meta.encryption.as_ref().unwrap().decrypted;
+    assert_eq!(read(body, &meta.files[0]), b"Hello, world!");
+}
+
+#[test]
+fn convert_drops_extensions() {
+    let original = create_v3(
+        &Extensions {
+            entry_checksums: true,
+            ..Default::default()
+        },
+        false,
+    );
+
+    let archive = convert_to(&original, None, 3).unwrap();
+    assert!(read_meta_with(&archive, None).files[0].checksum.is_some());
+
+    let archive = convert_to(&original, None, 1).unwrap();
+    let meta = read_meta_with(&archive, None);
+    assert_eq!(meta.files[0].checksum, None);
+    assert_eq!(read(&archive, &meta.files[0]), b"Hello, world!");
+}
+
+#[test]
+fn convert_invalid_version() {
+    let original = std::fs::read("tests/samples/wfld-normal.hssp").unwrap();
+    for version in [0, 4] {
+        let error = convert_to(&original, None, version).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,42 @@
+use hssp2::{metadata, ArchiveError};
+
+fn decryption_error(archive: &[u8]) -> ArchiveError {
+    let err = metadata(&mut dh::data::read_ref(archive), Some("Password")).unwrap_err();
+    *err.get_ref()
+        .unwrap()
+        .downcast_ref::<ArchiveError>()
+        .unwrap()
+}
+
+#[test]
+fn decryption_truncated() {
+    let archive = std::fs::read("tests/samples/wfld-encrypted.hssp").unwrap();
+
+    assert_eq!(
+        decryption_error(&archive[..archive.len() - 3]),
+        ArchiveError::TruncatedCiphertext
+    );
+    assert_eq!(
+        decryption_error(&archive[..64]),
+        ArchiveError::TruncatedCiphertext
+    );
+}
+
+#[test]
+fn decryption_bad_padding() {
+    let mut archive = std::fs::read("tests/samples/wfld-encrypted.hssp").unwrap();
+    // The body is 39 bytes, so the last plaintext byte is a padding length of 9.
+    // Flipping the matching ciphertext byte of the previous block zeroes it.
+    archive[64 + 31] ^= 9;
+
+    assert_eq!(decryption_error(&archive), ArchiveError::BadPadding);
+}
+
+#[test]
+fn decryption_undecryptable() {
+    let mut archive = std::fs::read("tests/samples/wfld-encrypted.hssp").unwrap();
+    // Flipping an IV byte flips the low byte of the first entry's path length.
+    archive[44 + 8] ^= 0xff;
+
+    assert_eq!(decryption_error(&archive), ArchiveError::Undecryptable);
+}
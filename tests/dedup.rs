@@ -0,0 +1,150 @@
+mod common;
+
+use common::read;
+use hssp2::{
+    append, convert, create_extended, remove, verify_detailed, write_hash, Compression, Entry,
+    EntryIntegrity, EntryWithSource, Extensions, File, Metadata,
+};
+use std::io::ErrorKind;
+
+const DATA: &[u8] = b"The same content under several paths.";
+
+fn create(entries: &[(&str, &[u8], Compression)], deduplicate: bool) -> Vec<u8> {
+    let files: Vec<Entry> = entries
+        .iter()
+        .map(|(path, data, compression)| Entry {
+            compression: *compression,
+            ..Entry::from(File {
+                path: path.to_string(),
+                length: data.len() as u64,
+                ..Default::default()
+            })
+        })
+        .collect();
+    let mut readers: Vec<_> = entries
+        .iter()
+        .map(|(_, data, _)| dh::data::read_ref(data))
+        .collect();
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| EntryWithSource(file, reader))
+        .collect();
+
+    let mut target = dh::data::rw_empty();
+    let result = create_extended(
+        3,
+        sources,
+        None,
+        None,
+        Some(&Extensions {
+            deduplicate,
+            entry_checksums: true,
+            ..Default::default()
+        }),
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+    dh::data::close(target)
+}
+
+fn read_meta(archive: &[u8]) -> Metadata {
+    let meta = common::read_meta(archive);
+    let report = verify_detailed(&mut dh::data::read_ref(archive), &meta).unwrap();
+    assert!(report.entries.iter().all(|e| *e == EntryIntegrity::Valid));
+    meta
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn dedup_create() {
+    let entries: &[(&str, &[u8], Compression)] = &[
+        ("a.txt", DATA, Compression::None),
+        ("b.txt", b"Other content", Compression::None),
+        ("c.txt", DATA, Compression::None),
+        ("d.txt", DATA, Compression::Zstd),
+        ("e.txt", DATA, Compression::Zstd),
+        ("empty.txt", b"", Compression::None),
+        ("empty2.txt", b"", Compression::None),
+    ];
+    let archive = create(entries, true);
+    // The duplicates record takes a 6 byte header and 8 bytes per duplicate.
+    assert_eq!(
+        create(entries, false).len() + 6 + 2 * 8 - archive.len(),
+        DATA.len() + read_meta(&archive).files[3].length as usize
+    );
+
+    let meta = read_meta(&archive);
+    assert!(meta.extensions.as_ref().unwrap().deduplicate);
+    let duplicates: Vec<_> = meta.files.iter().map(|f| f.duplicate_of).collect();
+    assert_eq!(duplicates, [None, None, Some(0), None, Some(3), None, None]);
+    assert_eq!(meta.files[2].offset, meta.files[0].offset);
+    assert_eq!(meta.files[2].length, meta.files[0].length);
+    assert_eq!(meta.files[4].compression, Compression::Zstd);
+    for (file, (_, data, _)) in meta.files.iter().zip(entries) {
+        assert_eq!(read(&archive, file), *data);
+    }
+}
+
+#[test]
+fn dedup_edit() {
+    let archive = create(
+        &[
+            ("a.txt", DATA, Compression::None),
+            ("b.txt", DATA, Compression::None),
+            ("c.txt", DATA, Compression::None),
+        ],
+        true,
+    );
+    let meta = read_meta(&archive);
+
+    // Removing the entry holding the data moves it to the next duplicate.
+    let mut target = dh::data::rw_empty();
+    let result = remove(
+        &mut dh::data::read_ref(&archive),
+        &meta,
+        &["a.txt"],
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+    let mut target = dh::data::rw(dh::data::close(target));
+
+    let file = Entry::from(File {
+        path: "d.txt".to_string(),
+        length: DATA.len() as u64,
+        ..Default::default()
+    });
+    append(
+        &mut target,
+        vec![EntryWithSource(&file, &mut dh::data::read_ref(DATA))],
+        1024,
+    )
+    .unwrap();
+    let edited = dh::data::close(target);
+
+    let meta = read_meta(&edited);
+    let duplicates: Vec<_> = meta.files.iter().map(|f| f.duplicate_of).collect();
+    assert_eq!(duplicates, [None, Some(0), None]);
+    for file in &meta.files {
+        assert_eq!(read(&edited, file), DATA);
+    }
+}
+
+#[test]
+fn dedup_convert() {
+    let archive = create(&[("a.txt", DATA, Compression::None)], true);
+    let mut target = dh::data::rw_empty();
+    let error = convert(
+        &mut dh::data::read_ref(&archive),
+        None,
+        2,
+        &mut target,
+        1024,
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
@@ -0,0 +1,230 @@
+mod common;
+
+use common::{read, read_meta};
+use hssp2::{
+    create, create_extended, metadata, remove, replace, replace_entry, write_hash, Attributes,
+    Entry, EntryWithSource, File, FileWithSource, Link,
+};
+use std::io::ErrorKind;
+
+fn create_with_main(main_file: u32) -> Vec<u8> {
+    let mut target = dh::data::rw_empty();
+    let mut a = dh::data::read_ref(b"a");
+    let mut b = dh::data::read_ref(b"bb");
+    let mut c = dh::data::read_ref(b"ccc");
+    let files: Vec<File> = [("a.txt", 1), ("b.txt", 2), ("c.txt", 3)]
+        .iter()
+        .map(|(path, length)| File {
+            path: path.to_string(),
+            length: *length,
+            ..Default::default()
+        })
+        .collect();
+
+    let result = create(
+        2,
+        vec![
+            FileWithSource(&files[0], &mut a),
+            FileWithSource(&files[1], &mut b),
+            FileWithSource(&files[2], &mut c),
+        ],
+        None,
+        Some(main_file),
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+    dh::data::close(target)
+}
+
+fn remove_from(archive: &[u8], paths: &[&str]) -> std::io::Result<Vec<u8>> {
+    let meta = metadata(&mut dh::data::read_ref(archive), None)?;
+    let mut target = dh::data::rw_empty();
+    let result = remove(
+        &mut dh::data::read_ref(archive),
+        &meta,
+        paths,
+        &mut target,
+        1024,
+    )?;
+    write_hash(&mut target, result)?;
+    Ok(dh::data::close(target))
+}
+
+fn replace_in(archive: &[u8], path: &str, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let meta = metadata(&mut dh::data::read_ref(archive), None)?;
+    let mut target = dh::data::rw_empty();
+    let result = replace(
+        &mut dh::data::read_ref(archive),
+        &meta,
+        path,
+        &mut dh::data::read_ref(data),
+        &mut target,
+        1024,
+    )?;
+    write_hash(&mut target, result)?;
+    Ok(dh::data::close(target))
+}
+
+#[test]
+fn remove_every_version() {
+    for (path, version) in [
+        ("tests/samples/wfld-multiple.hssp", 1),
+        ("tests/samples/rfld-multiple.hssp", 2),
+        ("tests/samples/dhdr-multiple.hssp", 3),
+    ] {
+        let archive = std::fs::read(path).unwrap();
+        let archive = remove_from(&archive, &["test.txt"]).unwrap();
+
+        let meta = read_meta(&archive);
+        assert_eq!(meta.version, version, "{}", path);
+        assert_eq!(meta.files.len(), 1);
+        assert_eq!(meta.files[0].path, "test2.txt");
+        assert_eq!(read(&archive, &meta.files[0]), b"Hello, world! 2");
+    }
+}
+
+#[test]
+fn remove_directory() {
+    let archive = std::fs::read("tests/samples/rfld-folder.hssp").unwrap();
+    let archive = remove_from(&archive, &["test"]).unwrap();
+
+    assert!(read_meta(&archive).files.is_empty());
+}
+
+#[test]
+fn remove_main_file() {
+    let archive = create_with_main(1);
+
+    let removed = remove_from(&archive, &["a.txt"]).unwrap();
+    let meta = read_meta(&removed);
+    assert_eq!(meta.main_file, Some(0));
+    assert_eq!(meta.files[0].path, "b.txt");
+
+    let removed = remove_from(&archive, &["b.txt"]).unwrap();
+    assert_eq!(read_meta(&removed).main_file, None);
+
+    let removed = remove_from(&archive, &["c.txt"]).unwrap();
+    assert_eq!(read_meta(&removed).main_file, Some(1));
+}
+
+#[test]
+fn remove_missing() {
+    let archive = create_with_main(0);
+    let error = remove_from(&archive, &["a.txt", "d.txt"]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn replace_file() {
+    let archive = create_with_main(1);
+    let archive = replace_in(&archive, "b.txt", b"Replaced").unwrap();
+
+    let meta = read_meta(&archive);
+    assert_eq!(meta.main_file, Some(1));
+    assert_eq!(meta.files.len(), 3);
+    assert_eq!(read(&archive, &meta.files[0]), b"a");
+    assert_eq!(read(&archive, &meta.files[1]), b"Replaced");
+    assert_eq!(read(&archive, &meta.files[2]), b"ccc");
+}
+
+#[test]
+fn replace_keeps_attributes_and_links() {
+    let attributes = Attributes {
+        mtime: 1_000_000_000,
+        mode: 0o600,
+        ..Default::default()
+    };
+    let files = [
+        Entry {
+            attributes: Some(attributes),
+            ..Entry::from(File {
+                path: "a.txt".to_string(),
+                length: 1,
+                ..Default::default()
+            })
+        },
+        Entry {
+            link: Some(Link::Symbolic("a.txt".to_string())),
+            ..Entry::from(File {
+                path: "link".to_string(),
+                ..Default::default()
+            })
+        },
+    ];
+    let mut readers = [dh::data::read_ref(b"a"), dh::data::read_ref(b"")];
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| EntryWithSource(file, reader))
+        .collect();
+    let mut target = dh::data::rw_empty();
+    let result = create_extended(3, sources, None, None, None, &mut target, 1024).unwrap();
+    write_hash(&mut target, result).unwrap();
+    let archive = dh::data::close(target);
+
+    let archive = replace_in(&archive, "a.txt", b"Replaced").unwrap();
+    let archive = replace_in(&archive, "link", b"b.txt").unwrap();
+    let meta = read_meta(&archive);
+    assert_eq!(meta.files[0].attributes, Some(attributes));
+    assert_eq!(read(&archive, &meta.files[0]), b"Replaced");
+    assert_eq!(
+        meta.files[1].link,
+        Some(Link::Symbolic("b.txt".to_string()))
+    );
+
+    // Attributes and links of the replacement take precedence.
+    let replacement = Entry {
+        attributes: Some(Attributes {
+            mode: 0o644,
+            ..attributes
+        }),
+        link: Some(Link::Hard("link".to_string())),
+        ..Entry::from(File {
+            path: "ignored".to_string(),
+            length: 1,
+            ..Default::default()
+        })
+    };
+    let mut target = dh::data::rw_empty();
+    let result = replace_entry(
+        &mut dh::data::read_ref(&archive),
+        &meta,
+        "a.txt",
+        EntryWithSource(&replacement, &mut dh::data::read_ref(b"c")),
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+    let archive = dh::data::close(target);
+    let meta = read_meta(&archive);
+    assert_eq!(meta.files[0].path, "a.txt");
+    assert_eq!(meta.files[0].attributes.unwrap().mode, 0o644);
+    assert_eq!(meta.files[0].link, Some(Link::Hard("link".to_string())));
+}
+
+#[test]
+fn replace_missing() {
+    let archive = std::fs::read("tests/samples/rfld-folder.hssp").unwrap();
+    // Directories have no data to replace.
+    let error = replace_in(&archive, "test", b"Replaced").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn edit_encrypted() {
+    let archive = std::fs::read("tests/samples/rfld-encrypted.hssp").unwrap();
+    let meta = metadata(&mut dh::data::read_ref(&archive), Some("Password")).unwrap();
+    let mut target = dh::data::rw_empty();
+    let error = remove(
+        &mut dh::data::read_ref(&archive),
+        &meta,
+        &["test.txt"],
+        &mut target,
+        1024,
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+}
@@ -0,0 +1,528 @@
+mod common;
+
+use common::read_meta;
+use hssp2::{
+    append, create, create_extended, pack, unpack, write_hash, AttributePolicy, Attributes, Entry,
+    EntryWithSource, File, FileWithSource, Link, LinkPolicy,
+};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+fn pack_dir(version: u8, directory: &Path) -> Vec<u8> {
+    let mut target = dh::data::rw_empty();
+    let result = pack(version, directory, None, None, &mut target, 1024).unwrap();
+    write_hash(&mut target, result).unwrap();
+    dh::data::close(target)
+}
+
+fn set_mtime(path: &Path, seconds: u64) {
+    fs::File::open(path)
+        .unwrap()
+        .set_modified(UNIX_EPOCH + Duration::from_secs(seconds))
+        .unwrap();
+}
+
+fn mtime(path: &Path) -> i64 {
+    fs::metadata(path)
+        .unwrap()
+        .modified()
+        .unwrap()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+fn source_dir() -> tempfile::TempDir {
+    let source = tempfile::tempdir().unwrap();
+    fs::create_dir_all(source.path().join("bin")).unwrap();
+    fs::create_dir_all(source.path().join("empty")).unwrap();
+    fs::write(source.path().join("bin/run.sh"), b"#!/bin/sh\necho hi\n").unwrap();
+    fs::write(source.path().join("readme.txt"), b"Hello, world!").unwrap();
+    set_mtime(&source.path().join("bin/run.sh"), 1_000_000_000);
+    set_mtime(&source.path().join("readme.txt"), 1_100_000_000);
+    set_mtime(&source.path().join("bin"), 1_200_000_000);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path: &str, mode| {
+            fs::set_permissions(source.path().join(path), fs::Permissions::from_mode(mode)).unwrap()
+        };
+        mode("bin/run.sh", 0o755);
+        mode("readme.txt", 0o640);
+        mode("bin", 0o750);
+    }
+    source
+}
+
+#[test]
+fn fs_pack() {
+    let source = source_dir();
+    let archive = pack_dir(3, source.path());
+    let meta = read_meta(&archive);
+
+    let paths: Vec<_> = meta
+        .files
+        .iter()
+        .map(|f| (f.path.as_str(), f.directory))
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            ("bin", true),
+            ("bin/run.sh", false),
+            ("empty", true),
+            ("readme.txt", false)
+        ]
+    );
+    let attributes = meta.files[1].attributes.unwrap();
+    assert_eq!(attributes.mtime, 1_000_000_000);
+    #[cfg(unix)]
+    assert_eq!(attributes.mode, 0o755);
+    assert_eq!(meta.files[0].attributes.unwrap().mtime, 1_200_000_000);
+
+    // Legacy versions cannot store attributes.
+    let meta = read_meta(&pack_dir(2, source.path()));
+    assert!(meta.files.iter().all(|f| f.attributes.is_none()));
+}
+
+#[test]
+fn fs_unpack() {
+    let source = source_dir();
+    let archive = pack_dir(3, source.path());
+    let meta = read_meta(&archive);
+
+    let target = tempfile::tempdir().unwrap();
+    unpack(
+        &mut dh::data::read_ref(&archive),
+        &meta,
+        target.path(),
+        LinkPolicy::Reject,
+        AttributePolicy::Permissions,
+        1024,
+    )
+    .unwrap();
+
+    let target = target.path();
+    assert_eq!(
+        fs::read(target.join("bin/run.sh")).unwrap(),
+        b"#!/bin/sh\necho hi\n"
+    );
+    assert_eq!(
+        fs::read(target.join("readme.txt")).unwrap(),
+        b"Hello, world!"
+    );
+    assert!(target.join("empty").is_dir());
+    for path in ["bin/run.sh", "readme.txt", "bin"] {
+        assert_eq!(mtime(&target.join(path)), mtime(&source.path().join(path)));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        for path in ["bin/run.sh", "readme.txt", "bin"] {
+            assert_eq!(
+                fs::metadata(target.join(path))
+                    .unwrap()
+                    .permissions()
+                    .mode()
+                    & 0o7777,
+                fs::metadata(source.path().join(path))
+                    .unwrap()
+                    .permissions()
+                    .mode()
+                    & 0o7777
+            );
+        }
+    }
+}
+
+#[test]
+fn fs_unpack_unsafe_paths() {
+    for path in [
+        "../escaped.txt",
+        "a/../../escaped.txt",
+        "/escaped.txt",
+        "a//b",
+        "./a",
+    ] {
+        let file = File {
+            path: path.to_string(),
+            length: 1,
+            ..Default::default()
+        };
+        let mut target = dh::data::rw_empty();
+        let result = create(
+            2,
+            vec![FileWithSource(&file, &mut dh::data::read_ref(b"a"))],
+            None,
+            None,
+            &mut target,
+            1024,
+        )
+        .unwrap();
+        write_hash(&mut target, result).unwrap();
+        let archive = dh::data::close(target);
+        let meta = read_meta(&archive);
+
+        let parent = tempfile::tempdir().unwrap();
+        let directory = parent.path().join("target");
+        let error = unpack(
+            &mut dh::data::read_ref(&archive),
+            &meta,
+            &directory,
+            LinkPolicy::Create,
+            AttributePolicy::Permissions,
+            1024,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", path);
+        assert!(!parent.path().join("escaped.txt").exists());
+        assert!(!directory.exists());
+    }
+}
+
+#[test]
+fn fs_attributes_append() {
+    let mut archive = dh::data::rw(fs::read("tests/samples/dhdr-multiple.hssp").unwrap());
+    let attributes = Attributes {
+        mtime: -1,
+        mode: 0o4755,
+        uid: 1000,
+        gid: 100,
+    };
+    let file = Entry {
+        attributes: Some(attributes),
+        ..Entry::from(File {
+            path: "new.txt".to_string(),
+            length: 1,
+            ..Default::default()
+        })
+    };
+    append(
+        &mut archive,
+        vec![EntryWithSource(&file, &mut dh::data::read_ref(b"a"))],
+        1024,
+    )
+    .unwrap();
+
+    let meta = read_meta(&dh::data::close(archive));
+    let read: Vec<_> = meta.files.iter().map(|f| f.attributes).collect();
+    assert_eq!(read, [None, None, Some(attributes)]);
+}
+
+#[cfg(unix)]
+#[test]
+fn fs_attribute_policy() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let attributes = Attributes {
+        mtime: 1_000_000_000,
+        mode: 0o6750,
+        uid: 12345,
+        gid: 12345,
+    };
+    let file = Entry {
+        attributes: Some(attributes),
+        ..Entry::from(File {
+            path: "run".to_string(),
+            length: 1,
+            ..Default::default()
+        })
+    };
+    let mut target = dh::data::rw_empty();
+    let result = create_extended(
+        3,
+        vec![EntryWithSource(&file, &mut dh::data::read_ref(b"a"))],
+        None,
+        None,
+        None,
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+    let archive = dh::data::close(target);
+    let meta = read_meta(&archive);
+
+    let unpack_with = |policy| {
+        let directory = tempfile::tempdir().unwrap();
+        unpack(
+            &mut dh::data::read_ref(&archive),
+            &meta,
+            directory.path(),
+            LinkPolicy::Reject,
+            policy,
+            1024,
+        )
+        .unwrap();
+        let metadata = fs::metadata(directory.path().join("run")).unwrap();
+        let owner = fs::metadata(directory.path()).unwrap().uid();
+        (metadata, owner)
+    };
+
+    let (metadata, _) = unpack_with(AttributePolicy::Timestamps);
+    let modified = metadata.modified().unwrap().duration_since(UNIX_EPOCH);
+    assert_eq!(modified.unwrap().as_secs(), 1_000_000_000);
+    assert_ne!(metadata.permissions().mode() & 0o7777, 0o750);
+
+    let (metadata, owner) = unpack_with(AttributePolicy::Permissions);
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o750);
+    assert_eq!(metadata.uid(), owner);
+
+    let (metadata, _) = unpack_with(AttributePolicy::All);
+    assert_eq!(metadata.permissions().mode() & 0o6000, 0o6000);
+}
+
+fn link_archive(entries: &[(&str, Option<Link>)]) -> Vec<u8> {
+    let files: Vec<Entry> = entries
+        .iter()
+        .map(|(path, link)| Entry {
+            link: link.clone(),
+            ..Entry::from(File {
+                path: path.trim_end_matches('/').to_string(),
+                directory: path.ends_with('/'),
+                length: 1,
+                ..Default::default()
+            })
+        })
+        .collect();
+    let mut readers: Vec<_> = files.iter().map(|_| dh::data::read_ref(b"a")).collect();
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| EntryWithSource(file, reader))
+        .collect();
+
+    let mut target = dh::data::rw_empty();
+    let result = create_extended(3, sources, None, None, None, &mut target, 1024).unwrap();
+    write_hash(&mut target, result).unwrap();
+    dh::data::close(target)
+}
+
+fn unpack_links(archive: &[u8], links: LinkPolicy) -> (tempfile::TempDir, std::io::Result<()>) {
+    let meta = read_meta(archive);
+    let parent = tempfile::tempdir().unwrap();
+    let result = unpack(
+        &mut dh::data::read_ref(archive),
+        &meta,
+        &parent.path().join("target"),
+        links,
+        AttributePolicy::Permissions,
+        1024,
+    );
+    (parent, result)
+}
+
+fn symbolic(target: &str) -> Option<Link> {
+    Some(Link::Symbolic(target.to_string()))
+}
+
+#[test]
+fn fs_links() {
+    let entries = [
+        ("dir/", None),
+        ("dir/file.txt", None),
+        ("file-link", symbolic("dir/file.txt")),
+        ("dir-link", symbolic("dir")),
+        ("dir/up", symbolic("../file-link")),
+        ("hard.txt", Some(Link::Hard("dir/file.txt".to_string()))),
+        ("dangling", symbolic("missing")),
+    ];
+    let archive = link_archive(&entries);
+    let meta = read_meta(&archive);
+    assert_eq!(meta.files[2].link, symbolic("dir/file.txt"));
+    assert_eq!(meta.files[1].link, None);
+    // The target is stored as the data of a link.
+    assert_eq!(meta.files[2].length, "dir/file.txt".len() as u64);
+
+    let (parent, result) = unpack_links(&archive, LinkPolicy::Reject);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+    assert!(!parent.path().join("target").exists());
+
+    #[cfg(unix)]
+    {
+        let (parent, result) = unpack_links(&archive, LinkPolicy::Create);
+        result.unwrap();
+        let target = parent.path().join("target");
+        assert_eq!(
+            fs::read_link(target.join("dir/up")).unwrap(),
+            Path::new("../file-link")
+        );
+        assert_eq!(fs::read(target.join("dir/up")).unwrap(), b"a");
+        assert_eq!(fs::read(target.join("dir-link/file.txt")).unwrap(), b"a");
+        assert!(fs::symlink_metadata(target.join("dangling"))
+            .unwrap()
+            .is_symlink());
+
+        use std::os::unix::fs::MetadataExt;
+        assert_eq!(
+            fs::metadata(target.join("hard.txt")).unwrap().ino(),
+            fs::metadata(target.join("dir/file.txt")).unwrap().ino()
+        );
+    }
+
+    // Copies need existing targets.
+    let (_, result) = unpack_links(&archive, LinkPolicy::Copy);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+
+    let archive = link_archive(&entries[..6]);
+    let (parent, result) = unpack_links(&archive, LinkPolicy::Copy);
+    result.unwrap();
+    let target = parent.path().join("target");
+    for path in [
+        "file-link",
+        "dir/up",
+        "hard.txt",
+        "dir-link/file.txt",
+        "dir-link/up",
+    ] {
+        let metadata = fs::symlink_metadata(target.join(path)).unwrap();
+        assert!(metadata.is_file(), "{}", path);
+        assert_eq!(fs::read(target.join(path)).unwrap(), b"a");
+    }
+}
+
+#[test]
+fn fs_links_unsafe() {
+    for entries in [
+        vec![("escape", symbolic("../escaped"))],
+        vec![("escape", symbolic("/tmp"))],
+        vec![("dir/", None), ("dir/escape", symbolic("../../escaped"))],
+        // Resolving ".." after following a link leaves the archive.
+        vec![("escape", symbolic("root/..")), ("root", symbolic("."))],
+        vec![
+            ("escape", symbolic("dir/root/dir/root/..")),
+            ("dir/", None),
+            ("dir/root", symbolic("..")),
+        ],
+    ] {
+        let archive = link_archive(&entries);
+        for links in [LinkPolicy::Create, LinkPolicy::Copy] {
+            let (parent, result) = unpack_links(&archive, links);
+            assert_eq!(
+                result.unwrap_err().kind(),
+                ErrorKind::InvalidData,
+                "{:?}",
+                entries
+            );
+            assert!(!parent.path().join("target").exists());
+        }
+    }
+
+    for entries in [
+        vec![("loop", symbolic("loop"))],
+        vec![("dir/", None), ("dir/self", symbolic("."))],
+    ] {
+        let (_, result) = unpack_links(&link_archive(&entries), LinkPolicy::Copy);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    // Every level of links to the level below would double the output.
+    let mut entries = vec![("0/".to_string(), None), ("0/file".to_string(), None)];
+    for level in 1..32 {
+        entries.push((format!("{}/", level), None));
+        for name in ["a", "b"] {
+            entries.push((
+                format!("{}/{}", level, name),
+                symbolic(&format!("../{}", level - 1)),
+            ));
+        }
+    }
+    let entries: Vec<_> = entries
+        .iter()
+        .map(|(path, link)| (path.as_str(), link.clone()))
+        .collect();
+    let (_, result) = unpack_links(&link_archive(&entries), LinkPolicy::Copy);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+
+    // Links to the same directory are each copied.
+    let archive = link_archive(&[
+        ("dir/", None),
+        ("dir/file", None),
+        ("a", symbolic("dir")),
+        ("b", symbolic("dir")),
+    ]);
+    let (parent, result) = unpack_links(&archive, LinkPolicy::Copy);
+    result.unwrap();
+    for path in ["a/file", "b/file"] {
+        assert_eq!(
+            fs::read(parent.path().join("target").join(path)).unwrap(),
+            b"a"
+        );
+    }
+
+    for link in [
+        Some(Link::Hard("dir".to_string())),
+        Some(Link::Hard("link".to_string())),
+    ] {
+        let archive = link_archive(&[("dir/", None), ("link", symbolic("dir")), ("hard", link)]);
+        let (_, result) = unpack_links(&archive, LinkPolicy::Create);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn fs_links_version() {
+    let file = Entry {
+        link: symbolic("target"),
+        ..Entry::from(File {
+            path: "link".to_string(),
+            ..Default::default()
+        })
+    };
+    let mut target = dh::data::rw_empty();
+    let error = create_extended(
+        2,
+        vec![EntryWithSource(&file, &mut dh::data::read_ref(b""))],
+        None,
+        None,
+        None,
+        &mut target,
+        1024,
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+#[cfg(unix)]
+#[test]
+fn fs_pack_links() {
+    let source = tempfile::tempdir().unwrap();
+    fs::create_dir(source.path().join("dir")).unwrap();
+    fs::write(source.path().join("dir/file.txt"), b"Hello, world!").unwrap();
+    fs::hard_link(
+        source.path().join("dir/file.txt"),
+        source.path().join("hard.txt"),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink("dir/file.txt", source.path().join("link")).unwrap();
+
+    let archive = pack_dir(3, source.path());
+    let meta = read_meta(&archive);
+    let entries: Vec<_> = meta
+        .files
+        .iter()
+        .map(|f| (f.path.as_str(), f.link.clone()))
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            ("dir", None),
+            ("dir/file.txt", None),
+            ("hard.txt", Some(Link::Hard("dir/file.txt".to_string()))),
+            ("link", symbolic("dir/file.txt")),
+        ]
+    );
+
+    // Legacy versions store hard links as files, but cannot store symbolic links.
+    let mut target = dh::data::rw_empty();
+    let error = pack(2, source.path(), None, None, &mut target, 1024).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    fs::remove_file(source.path().join("link")).unwrap();
+    let meta = read_meta(&pack_dir(2, source.path()));
+    assert_eq!(meta.files[2].link, None);
+    assert_eq!(meta.files[2].length, 13);
+}
@@ -0,0 +1,75 @@
+// Regression tests for inputs found by the targets in `fuzz/`.
+
+use hssp2::{create, File, FileWithSource};
+
+#[test]
+fn fuzz_file_path_with_directory_prefix() {
+    let mut target = dh::data::rw_empty();
+    let mut test_txt = dh::data::read_ref(b"Hello, world!");
+
+    assert!(create(
+        1,
+        vec![FileWithSource(
+            &File {
+                path: "//G\n".to_string(),
+                directory: false,
+                offset: 0,
+                length: 13,
+            },
+            &mut test_txt,
+        )],
+        None,
+        None,
+        &mut target,
+        1024,
+    )
+    .is_err());
+}
+
+#[test]
+fn fuzz_path_too_long() {
+    let mut target = dh::data::rw_empty();
+    let mut test = dh::data::read(vec![]);
+
+    assert!(create(
+        1,
+        vec![FileWithSource(
+            &File {
+                path: "a".repeat(u16::MAX as usize - 1),
+                directory: true,
+                offset: 0,
+                length: 0,
+            },
+            &mut test,
+        )],
+        None,
+        None,
+        &mut target,
+        1024,
+    )
+    .is_err());
+}
+
+#[test]
+fn fuzz_empty_path() {
+    let mut target = dh::data::rw_empty();
+    let mut test = dh::data::read(vec![]);
+
+    assert!(create(
+        2,
+        vec![FileWithSource(
+            &File {
+                path: "".to_string(),
+                directory: false,
+                offset: 0,
+                length: 0,
+            },
+            &mut test,
+        )],
+        None,
+        None,
+        &mut target,
+        1024,
+    )
+    .is_err());
+}
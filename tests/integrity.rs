@@ -0,0 +1,145 @@
+use hssp2::{
+    create_extended, metadata, verify_detailed, verify_entry, write_hash, EntryIntegrity,
+    EntryWithSource, Extensions, File,
+};
+
+fn create_with_checksums(encryption: Option<(&str, &[u8; 16])>) -> Vec<u8> {
+    let mut target = dh::data::rw_empty();
+    let mut test_txt = dh::data::read_ref(b"Hello, world!");
+    let mut test2_txt = dh::data::read_ref(b"Hello, world! 2");
+
+    let result = create_extended(
+        3,
+        vec![
+            EntryWithSource(
+                &File {
+                    path: "test.txt".to_string(),
+                    directory: false,
+                    offset: 0,
+                    length: 13,
+                }
+                .into(),
+                &mut test_txt,
+            ),
+            EntryWithSource(
+                &File {
+                    path: "test2.txt".to_string(),
+                    directory: false,
+                    offset: 0,
+                    length: 15,
+                }
+                .into(),
+                &mut test2_txt,
+            ),
+        ],
+        encryption,
+        None,
+        Some(&Extensions {
+            entry_checksums: true,
+            ..Default::default()
+        }),
+        &mut target,
+        1024,
+    )
+    .unwrap();
+
+    write_hash(&mut target, result).unwrap();
+    dh::data::close(target)
+}
+
+#[test]
+fn integrity_corrupted_sample() {
+    let mut reader = dh::file::open_r("tests/samples/dhdr-corrupted.hssp").unwrap();
+    let meta = metadata(&mut reader, None).unwrap();
+    let report = verify_detailed(&mut reader, &meta).unwrap();
+
+    assert!(!report.checksum);
+    assert_eq!(report.entries, vec![EntryIntegrity::Unverified]);
+}
+
+#[test]
+fn integrity_valid() {
+    let archive = create_with_checksums(None);
+    let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+
+    assert!(meta.extensions.as_ref().unwrap().entry_checksums);
+    assert!(meta.files.iter().all(|f| f.checksum.is_some()));
+    let report = verify_detailed(&mut dh::data::read_ref(&archive), &meta).unwrap();
+    assert!(report.checksum);
+    assert_eq!(report.entries, vec![EntryIntegrity::Valid; 2]);
+}
+
+#[test]
+fn integrity_corrupted_entry() {
+    let mut archive = create_with_checksums(None);
+    let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+    archive[meta.files[1].offset as usize + 3] ^= 1;
+
+    let report = verify_detailed(&mut dh::data::read_ref(&archive), &meta).unwrap();
+    assert!(!report.checksum);
+    assert_eq!(report.entries[0], EntryIntegrity::Valid);
+    assert_eq!(
+        report.entries[1],
+        EntryIntegrity::Corrupted {
+            offset: meta.files[1].offset,
+            length: 15
+        }
+    );
+
+    assert_eq!(
+        verify_entry(&mut dh::data::read_ref(&archive), &meta.files[0]).unwrap(),
+        Some(true)
+    );
+    assert_eq!(
+        verify_entry(&mut dh::data::read_ref(&archive), &meta.files[1]).unwrap(),
+        Some(false)
+    );
+}
+
+#[test]
+fn integrity_corrupted_padding() {
+    let mut reader = dh::file::open_r("tests/samples/wfld-multiple.hssp").unwrap();
+    let meta = metadata(&mut reader, None).unwrap();
+    let mut archive = std::fs::read("tests/samples/wfld-multiple.hssp").unwrap();
+    let padding = (meta.files[0].offset + meta.files[0].length) as usize;
+    archive[padding + 2] = 0xff;
+
+    let report = verify_detailed(&mut dh::data::read_ref(&archive), &meta).unwrap();
+    assert!(!report.checksum);
+    assert_eq!(
+        report.entries,
+        vec![
+            EntryIntegrity::Corrupted {
+                offset: padding as u64,
+                length: 8
+            },
+            EntryIntegrity::Unverified
+        ]
+    );
+}
+
+#[test]
+fn integrity_encrypted() {
+    let archive = create_with_checksums(Some(("Password", &[1; 16])));
+    let meta = metadata(&mut dh::data::read_ref(&archive), Some("Password")).unwrap();
+
+    let report = verify_detailed(&mut dh::data::read_ref(&archive), &meta).unwrap();
+    assert!(report.checksum);
+    assert_eq!(report.entries, vec![EntryIntegrity::Valid; 2]);
+}
+
+#[test]
+fn integrity_encrypted_checksums_hidden() {
+    let archive = create_with_checksums(Some(("Password", &[1; 16])));
+    let meta = metadata(&mut dh::data::read_ref(&archive), Some("Password")).unwrap();
+
+    for file in &meta.files {
+        let checksum = file.checksum.unwrap().to_le_bytes();
+        assert!(!archive.windows(4).any(|w| w == checksum));
+    }
+    let decrypted = &meta.encryption.as_ref().unwrap().decrypted;
+    assert_eq!(
+        verify_entry(&mut dh::data::read_ref(decrypted), &meta.files[1]).unwrap(),
+        Some(true)
+    );
+}
@@ -0,0 +1,204 @@
+use dh::recommended::*;
+use hssp2::{
+    create, create_extended, extract, metadata, metadata_with_limits, verify_integrity, write_hash,
+    ArchiveError, EntryWithSource, Extensions, File, FileWithSource, Kdf, Limits,
+};
+
+fn create_with_kdf(kdf: Kdf) -> Vec<u8> {
+    let mut target = dh::data::rw_empty();
+    let mut test_txt = dh::data::read_ref(b"Hello, world!");
+
+    let result = create_extended(
+        3,
+        vec![EntryWithSource(
+            &File {
+                path: "test.txt".to_string(),
+                directory: false,
+                offset: 0,
+                length: 13,
+            }
+            .into(),
+            &mut test_txt,
+        )],
+        Some(("Password", &[1; 16])),
+        None,
+        Some(&Extensions {
+            kdf,
+            ..Default::default()
+        }),
+        &mut target,
+        1024,
+    )
+    .unwrap();
+
+    write_hash(&mut target, result).unwrap();
+    dh::data::close(target)
+}
+
+#[test]
+fn kdf_pbkdf2() {
+    let kdf = Kdf::Pbkdf2 {
+        iterations: 1000,
+        salt: [2; 16],
+    };
+    let archive = create_with_kdf(kdf);
+
+    let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+    assert_eq!(meta.version, 3);
+    assert_eq!(meta.extensions.unwrap().kdf, kdf);
+    assert!(meta.files.is_empty());
+
+    let meta = metadata(&mut dh::data::read_ref(&archive), Some("password")).unwrap();
+    let enc = meta.encryption.unwrap();
+    assert_ne!(enc.hash, enc.hash_expected);
+
+    let meta = metadata(&mut dh::data::read_ref(&archive), Some("Password")).unwrap();
+    assert!(verify_integrity(&mut dh::data::read_ref(&archive), &meta).unwrap());
+    assert_eq!(meta.files.len(), 1);
+    assert_eq!(meta.files[0].path, "test.txt");
+    assert_eq!(meta.files[0].length, 13);
+
+    let enc = meta.encryption.unwrap();
+    assert_eq!(enc.hash, enc.hash_expected);
+    let mut target = dh::data::write_new(meta.files[0].length);
+    extract(
+        &mut dh::data::read_ref(&enc.decrypted),
+        &meta.files[0],
+        &mut target,
+        1024,
+        0,
+    )
+    .unwrap();
+    assert_eq!(dh::data::close(target), b"Hello, world!");
+}
+
+#[test]
+fn kdf_argon2() {
+    let kdf = Kdf::Argon2 {
+        memory: 64,
+        iterations: 1,
+        parallelism: 1,
+        salt: [3; 16],
+    };
+    let archive = create_with_kdf(kdf);
+
+    let meta = metadata(&mut dh::data::read_ref(&archive), Some("Password")).unwrap();
+    assert!(verify_integrity(&mut dh::data::read_ref(&archive), &meta).unwrap());
+    assert_eq!(meta.extensions.unwrap().kdf, kdf);
+    let enc = meta.encryption.unwrap();
+    assert_eq!(enc.hash, enc.hash_expected);
+    assert_eq!(meta.files.len(), 1);
+    assert_eq!(meta.files[0].path, "test.txt");
+}
+
+#[test]
+fn kdf_hash_differs_from_legacy() {
+    let archive = create_with_kdf(Kdf::Pbkdf2 {
+        iterations: 1000,
+        salt: [2; 16],
+    });
+    let mut legacy = dh::data::rw_empty();
+    let mut test_txt = dh::data::read_ref(b"Hello, world!");
+    create(
+        3,
+        vec![FileWithSource(
+            &File {
+                path: "test.txt".to_string(),
+                directory: false,
+                offset: 0,
+                length: 13,
+            },
+            &mut test_txt,
+        )],
+        Some(("Password", &[1; 16])),
+        None,
+        &mut legacy,
+        1024,
+    )
+    .unwrap();
+    let legacy = dh::data::close(legacy);
+
+    assert_ne!(archive[12..44], legacy[12..44]);
+}
+
+#[test]
+fn kdf_legacy_archive_has_no_extensions() {
+    let mut reader = dh::file::open_r("tests/samples/dhdr-encrypted.hssp").unwrap();
+    let meta = metadata(&mut reader, Some("Password")).unwrap();
+
+    assert_eq!(meta.version, 3);
+    assert!(meta.extensions.is_none());
+    let enc = meta.encryption.unwrap();
+    assert_eq!(enc.hash, enc.hash_expected);
+}
+
+#[test]
+fn kdf_requires_v3() {
+    let mut target = dh::data::rw_empty();
+
+    assert!(create_extended(
+        2,
+        vec![],
+        Some(("Password", &[1; 16])),
+        None,
+        Some(&Extensions::default()),
+        &mut target,
+        1024,
+    )
+    .is_err());
+}
+
+#[test]
+fn kdf_cost_limits() {
+    let error = |archive: &[u8], limits: &Limits| {
+        let error =
+            metadata_with_limits(&mut dh::data::read_ref(archive), None, limits).unwrap_err();
+        *error
+            .get_ref()
+            .unwrap()
+            .downcast_ref::<ArchiveError>()
+            .unwrap()
+    };
+
+    let mut archive = create_with_kdf(Kdf::Pbkdf2 {
+        iterations: 1000,
+        salt: [2; 16],
+    });
+    let limits = Limits {
+        max_pbkdf2_iterations: 999,
+        ..Default::default()
+    };
+    assert_eq!(error(&archive, &limits), ArchiveError::KdfTooExpensive);
+
+    // The iterations follow the record header and the KDF id.
+    let section = u64::from_le_bytes(archive[100..108].try_into().unwrap()) as usize;
+    archive[section + 7..section + 11].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+        error(&archive, &Limits::default()),
+        ArchiveError::KdfTooExpensive
+    );
+
+    let archive = create_with_kdf(Kdf::Argon2 {
+        memory: 64,
+        iterations: 2,
+        parallelism: 1,
+        salt: [3; 16],
+    });
+    for limits in [
+        Limits {
+            max_argon2_memory: 63,
+            ..Default::default()
+        },
+        Limits {
+            max_argon2_iterations: 1,
+            ..Default::default()
+        },
+        Limits {
+            max_argon2_parallelism: 0,
+            ..Default::default()
+        },
+    ] {
+        assert_eq!(error(&archive, &limits), ArchiveError::KdfTooExpensive);
+    }
+    assert!(metadata(&mut dh::data::read_ref(&archive), Some("Password")).is_ok());
+}
@@ -0,0 +1,90 @@
+use hssp2::{metadata, metadata_with_limits, ArchiveError, Limits};
+
+fn parse_error(archive: &[u8], limits: &Limits) -> ArchiveError {
+    let err = metadata_with_limits(&mut dh::data::read_ref(archive), None, limits).unwrap_err();
+    *err.get_ref()
+        .unwrap()
+        .downcast_ref::<ArchiveError>()
+        .unwrap()
+}
+
+#[test]
+fn limits_file_count_out_of_bounds() {
+    let mut archive = std::fs::read("tests/samples/wfld-normal.hssp").unwrap();
+    archive[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+
+    assert_eq!(
+        parse_error(&archive, &Limits::default()),
+        ArchiveError::OutOfBounds
+    );
+}
+
+#[test]
+fn limits_entry_size_out_of_bounds() {
+    let mut archive = std::fs::read("tests/samples/wfld-multiple.hssp").unwrap();
+    archive[64..72].copy_from_slice(&u64::MAX.to_le_bytes());
+
+    assert_eq!(
+        parse_error(&archive, &Limits::default()),
+        ArchiveError::OutOfBounds
+    );
+}
+
+#[test]
+fn limits_truncated() {
+    let archive = std::fs::read("tests/samples/wfld-multiple.hssp").unwrap();
+
+    assert_eq!(
+        parse_error(&archive[..archive.len() - 1], &Limits::default()),
+        ArchiveError::OutOfBounds
+    );
+}
+
+#[test]
+fn limits_extension_out_of_bounds() {
+    let mut archive = std::fs::read("tests/samples/dhdr-normal.hssp").unwrap();
+    archive[96..100].copy_from_slice(b"HSSX");
+    archive[100..108].copy_from_slice(&u64::MAX.to_le_bytes());
+
+    assert_eq!(
+        parse_error(&archive, &Limits::default()),
+        ArchiveError::OutOfBounds
+    );
+}
+
+#[test]
+fn limits_configured() {
+    let archive = std::fs::read("tests/samples/wfld-multiple.hssp").unwrap();
+
+    assert!(metadata(&mut dh::data::read_ref(&archive), None).is_ok());
+    assert_eq!(
+        parse_error(
+            &archive,
+            &Limits {
+                max_entries: 1,
+                ..Default::default()
+            }
+        ),
+        ArchiveError::TooManyEntries
+    );
+    assert_eq!(
+        parse_error(
+            &archive,
+            &Limits {
+                max_path_length: 8,
+                ..Default::default()
+            }
+        ),
+        ArchiveError::PathTooLong
+    );
+    assert_eq!(
+        parse_error(
+            &archive,
+            &Limits {
+                max_total_size: 20,
+                ..Default::default()
+            }
+        ),
+        ArchiveError::TooLarge
+    );
+}
@@ -0,0 +1,167 @@
+mod common;
+
+use common::read_meta;
+use hssp2::{
+    create_extended, find_main_file, metadata, set_main_file, write_hash, Entry, EntryWithSource,
+    File, Link,
+};
+use std::io::ErrorKind;
+
+fn files() -> Vec<Entry> {
+    vec![
+        Entry::from(File {
+            path: "bin".to_string(),
+            directory: true,
+            ..Default::default()
+        }),
+        Entry::from(File {
+            path: "bin/run.sh".to_string(),
+            length: 4,
+            ..Default::default()
+        }),
+        Entry::from(File {
+            path: "README".to_string(),
+            length: 4,
+            ..Default::default()
+        }),
+        Entry {
+            file: File {
+                path: "latest".to_string(),
+                ..Default::default()
+            },
+            link: Some(Link::Symbolic("bin/run.sh".to_string())),
+            ..Default::default()
+        },
+    ]
+}
+
+fn create_with_main(
+    version: u8,
+    files: &[Entry],
+    main_file: Option<u32>,
+) -> std::io::Result<Vec<u8>> {
+    let mut readers: Vec<_> = files.iter().map(|_| dh::data::read_ref(b"data")).collect();
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| EntryWithSource(file, reader))
+        .collect();
+    let mut target = dh::data::rw_empty();
+    let result = create_extended(version, sources, None, main_file, None, &mut target, 1024)?;
+    write_hash(&mut target, result)?;
+    Ok(dh::data::close(target))
+}
+
+#[test]
+fn main_file_by_path() {
+    let files = files();
+    let main_file = find_main_file(&files, "bin/run.sh").unwrap();
+    assert_eq!(main_file, 1);
+
+    let archive = create_with_main(3, &files, Some(main_file)).unwrap();
+    let meta = read_meta(&archive);
+    assert_eq!(meta.main_file, Some(1));
+    assert_eq!(meta.main_path(), Some("bin/run.sh"));
+
+    let archive = create_with_main(3, &files, None).unwrap();
+    assert_eq!(read_meta(&archive).main_path(), None);
+
+    let archive = std::fs::read("tests/samples/wfld-withmain.hssp").unwrap();
+    let meta = read_meta(&archive);
+    assert_eq!(meta.main_path(), Some(meta.files[0].path.as_str()));
+}
+
+#[test]
+fn find_main_file_invalid() {
+    let files = files();
+    let error = find_main_file(&files, "missing").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let error = find_main_file(&files, "bin").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = find_main_file(&files, "latest").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+    // A file takes precedence over a directory with the same path.
+    let files = [
+        Entry::from(File {
+            path: "app".to_string(),
+            directory: true,
+            ..Default::default()
+        }),
+        Entry::from(File {
+            path: "app".to_string(),
+            ..Default::default()
+        }),
+    ];
+    assert_eq!(find_main_file(&files, "app").unwrap(), 1);
+}
+
+#[test]
+fn create_invalid_main_file() {
+    let files = files();
+    for main_file in [0, 3, 4, u32::MAX] {
+        let error = create_with_main(3, &files, Some(main_file)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput, "{}", main_file);
+    }
+    let error = create_with_main(2, &[], Some(0)).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn read_invalid_main_file() {
+    let files = &files()[..3];
+    for (main, valid) in [
+        (0, true),
+        (1, false),
+        (2, true),
+        (4, false),
+        (u32::MAX, false),
+    ] {
+        let mut archive = create_with_main(2, files, None).unwrap();
+        archive[60..64].copy_from_slice(&main.to_le_bytes());
+        let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+        if valid {
+            assert_eq!(meta.main_file, main.checked_sub(1));
+        } else {
+            assert_eq!(meta.main_file, None, "{}", main);
+            assert_eq!(meta.main_path(), None);
+        }
+    }
+}
+
+#[test]
+fn set_main_file_by_path() {
+    let files = files();
+    let archive = create_with_main(3, &files, None).unwrap();
+
+    let set = |archive: &[u8], path| -> std::io::Result<Vec<u8>> {
+        let meta = read_meta(archive);
+        let mut target = dh::data::rw_empty();
+        let result = set_main_file(
+            &mut dh::data::read_ref(archive),
+            &meta,
+            path,
+            &mut target,
+            1024,
+        )?;
+        write_hash(&mut target, result)?;
+        Ok(dh::data::close(target))
+    };
+
+    let archive = set(&archive, Some("README")).unwrap();
+    let meta = read_meta(&archive);
+    assert_eq!(meta.main_path(), Some("README"));
+    assert_eq!(meta.files.len(), 4);
+
+    assert_eq!(
+        set(&archive, Some("missing")).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    assert_eq!(
+        set(&archive, Some("bin")).unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+
+    let archive = set(&archive, None).unwrap();
+    assert_eq!(read_meta(&archive).main_file, None);
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use common::{read, read_meta_with};
+use hssp2::{create, merge, write_hash, ArchiveWithPassword, ConflictPolicy, File, FileWithSource};
+use std::io::ErrorKind;
+
+fn create_archive(entries: &[(&str, &[u8])], main_file: Option<u32>) -> Vec<u8> {
+    let files: Vec<File> = entries
+        .iter()
+        .map(|(path, data)| File {
+            path: path.to_string(),
+            length: data.len() as u64,
+            ..Default::default()
+        })
+        .collect();
+    let mut readers: Vec<_> = entries
+        .iter()
+        .map(|(_, data)| dh::data::read_ref(data))
+        .collect();
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| FileWithSource(file, reader))
+        .collect();
+
+    let mut target = dh::data::rw_empty();
+    let result = create(2, sources, None, main_file, &mut target, 1024).unwrap();
+    write_hash(&mut target, result).unwrap();
+    dh::data::close(target)
+}
+
+fn merge_archives(
+    archives: &[(&[u8], Option<&str>)],
+    encryption: Option<(&str, &[u8; 16])>,
+    main_source: Option<usize>,
+    conflict_policy: ConflictPolicy,
+) -> std::io::Result<Vec<u8>> {
+    let mut readers: Vec<_> = archives
+        .iter()
+        .map(|(archive, _)| dh::data::read_ref(archive))
+        .collect();
+    let sources = readers
+        .iter_mut()
+        .zip(archives)
+        .map(|(reader, (_, password))| ArchiveWithPassword(reader, *password))
+        .collect();
+
+    let mut target = dh::data::rw_empty();
+    let result = merge(
+        3,
+        sources,
+        encryption,
+        main_source,
+        conflict_policy,
+        &mut target,
+        1024,
+    )?;
+    write_hash(&mut target, result)?;
+    Ok(dh::data::close(target))
+}
+
+#[test]
+fn merge_mixed_versions() {
+    let v1 = std::fs::read("tests/samples/wfld-normal.hssp").unwrap();
+    let v2_encrypted = std::fs::read("tests/samples/rfld-encrypted.hssp").unwrap();
+    let v3 = std::fs::read("tests/samples/dhdr-folder.hssp").unwrap();
+    let other = create_archive(&[("other.txt", b"Other")], None);
+
+    let archive = merge_archives(
+        &[
+            (&v2_encrypted, Some("Password")),
+            (&v1, None),
+            (&v3, None),
+            (&other, None),
+        ],
+        Some(("Merged", &[1; 16])),
+        None,
+        ConflictPolicy::FirstWins,
+    )
+    .unwrap();
+
+    let meta = read_meta_with(&archive, Some("Merged"));
+    let body = &meta.encryption.as_ref().unwrap().decrypted;
+    let paths: Vec<_> = meta.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, ["test.txt", "test", "test/test.txt", "other.txt"]);
+    assert!(meta.files[1].directory);
+    assert_eq!(read(body, &meta.files[0]), b"Hello, world!");
+    assert_eq!(read(body, &meta.files[2]), b"Hello, world!");
+    assert_eq!(read(body, &meta.files[3]), b"Other");
+}
+
+#[test]
+fn merge_conflicts() {
+    let first = create_archive(&[("a.txt", b"first"), ("b.txt", b"b")], None);
+    let last = create_archive(&[("c.txt", b"c"), ("a.txt", b"last")], None);
+    let sources: [(&[u8], Option<&str>); 2] = [(&first, None), (&last, None)];
+
+    let archive = merge_archives(&sources, None, None, ConflictPolicy::FirstWins).unwrap();
+    let meta = read_meta_with(&archive, None);
+    assert_eq!(meta.files.len(), 3);
+    assert_eq!(read(&archive, &meta.files[0]), b"first");
+
+    let archive = merge_archives(&sources, None, None, ConflictPolicy::LastWins).unwrap();
+    let meta = read_meta_with(&archive, None);
+    assert_eq!(meta.files.len(), 3);
+    assert_eq!(meta.files[0].path, "a.txt");
+    assert_eq!(read(&archive, &meta.files[0]), b"last");
+    assert_eq!(meta.files[2].path, "c.txt");
+
+    let error = merge_archives(&sources, None, None, ConflictPolicy::Error).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn merge_shared_directories() {
+    let folder = std::fs::read("tests/samples/rfld-folder.hssp").unwrap();
+    let other = std::fs::read("tests/samples/dhdr-folder.hssp").unwrap();
+
+    // The directories are merged, but both contain test/test.txt.
+    let error = merge_archives(
+        &[(&folder, None), (&other, None)],
+        None,
+        None,
+        ConflictPolicy::Error,
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert!(error.to_string().contains("test/test.txt"));
+}
+
+#[test]
+fn merge_main_file() {
+    let first = create_archive(&[("a.txt", b"a")], Some(0));
+    let second = create_archive(&[("b.txt", b"b"), ("c.txt", b"c")], Some(1));
+    let sources: [(&[u8], Option<&str>); 2] = [(&first, None), (&second, None)];
+
+    let archive = merge_archives(&sources, None, Some(1), ConflictPolicy::Error).unwrap();
+    assert_eq!(read_meta_with(&archive, None).main_file, Some(2));
+
+    let archive = merge_archives(&sources, None, Some(0), ConflictPolicy::Error).unwrap();
+    assert_eq!(read_meta_with(&archive, None).main_file, Some(0));
+
+    let archive = merge_archives(&sources, None, None, ConflictPolicy::Error).unwrap();
+    assert_eq!(read_meta_with(&archive, None).main_file, None);
+
+    let error = merge_archives(&sources, None, Some(2), ConflictPolicy::Error).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn merge_wrong_password() {
+    let encrypted = std::fs::read("tests/samples/rfld-encrypted.hssp").unwrap();
+
+    for password in [None, Some("password")] {
+        let error = merge_archives(&[(&encrypted, password)], None, None, ConflictPolicy::Error)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,151 @@
+#![cfg(feature = "mmap")]
+
+use hssp2::{
+    create_extended, write_hash, Entry, EntryWithSource, Extensions, File, Limits, MmapArchive,
+};
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+};
+
+fn write_archive(
+    entries: &[(&str, &[u8])],
+    password: Option<&str>,
+    extensions: Option<&Extensions>,
+) -> tempfile::NamedTempFile {
+    let files: Vec<Entry> = entries
+        .iter()
+        .map(|(path, data)| {
+            Entry::from(File {
+                path: path.to_string(),
+                length: data.len() as u64,
+                ..Default::default()
+            })
+        })
+        .collect();
+    let mut readers: Vec<_> = entries
+        .iter()
+        .map(|(_, data)| dh::data::read_ref(data))
+        .collect();
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| EntryWithSource(file, reader))
+        .collect();
+    let encryption = password.map(|password| (password, &[3; 16]));
+    let mut target = dh::data::rw_empty();
+    let version = if extensions.is_some() { 3 } else { 2 };
+    let result = create_extended(
+        version,
+        sources,
+        encryption,
+        None,
+        extensions,
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(&dh::data::close(target)).unwrap();
+    file
+}
+
+fn open(file: &fs::File, password: Option<&str>) -> std::io::Result<MmapArchive> {
+    // SAFETY: Test archives are temporary files nothing else writes to.
+    unsafe { MmapArchive::open(file, password) }
+}
+
+const ENTRIES: &[(&str, &[u8])] = &[("a.txt", b"Hello"), ("b.txt", b""), ("c.txt", b"world!")];
+
+#[test]
+fn mmap_entries() {
+    let file = write_archive(ENTRIES, None, None);
+    let archive = open(file.as_file(), None).unwrap();
+    assert!(archive.verify_integrity());
+    assert_eq!(archive.bytes(), fs::read(file.path()).unwrap());
+
+    let meta = archive.metadata();
+    assert_eq!(meta.files.len(), 3);
+    for (file, (_, data)) in meta.files.iter().zip(ENTRIES) {
+        let entry = archive.entry(file).unwrap();
+        assert_eq!(entry, *data);
+        // Borrowed from the mapping rather than copied.
+        assert!(archive.bytes().as_ptr_range().contains(&entry.as_ptr()) || entry.is_empty());
+    }
+    assert_eq!(archive.entry_by_path("c.txt").unwrap(), b"world!");
+    assert_eq!(
+        archive.entry_by_path("d.txt").unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+
+    let outside = Entry::from(File {
+        path: "outside".to_string(),
+        offset: archive.bytes().len() as u64 - 2,
+        length: 3,
+        ..Default::default()
+    });
+    assert_eq!(
+        archive.entry(&outside).unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+}
+
+#[test]
+fn mmap_encrypted() {
+    let file = write_archive(ENTRIES, Some("password"), None);
+    let archive = open(file.as_file(), Some("password")).unwrap();
+    assert!(archive.verify_integrity());
+    assert_eq!(archive.entry_by_path("a.txt").unwrap(), b"Hello");
+}
+
+#[cfg(all(feature = "deflate", feature = "zstd"))]
+#[test]
+fn mmap_compressed() {
+    use hssp2::Compression;
+
+    let extensions = Extensions {
+        body_compression: Compression::Zstd,
+        entry_checksums: true,
+        ..Default::default()
+    };
+    let file = write_archive(ENTRIES, None, Some(&extensions));
+    let archive = open(file.as_file(), None).unwrap();
+    assert!(archive.verify_integrity());
+    assert_eq!(archive.entry_by_path("c.txt").unwrap(), b"world!");
+    for file in &archive.metadata().files {
+        assert_eq!(archive.verify_entry(file).unwrap(), Some(true));
+    }
+
+    let extensions = Extensions {
+        compression: Compression::Deflate,
+        ..Default::default()
+    };
+    let file = write_archive(ENTRIES, None, Some(&extensions));
+    let archive = open(file.as_file(), None).unwrap();
+    assert_eq!(
+        archive.entry_by_path("a.txt").unwrap_err().kind(),
+        ErrorKind::Unsupported
+    );
+}
+
+#[test]
+fn mmap_corrupted() {
+    let file = write_archive(ENTRIES, None, None);
+    let mut bytes = fs::read(file.path()).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    fs::write(file.path(), bytes).unwrap();
+
+    let archive = open(file.as_file(), None).unwrap();
+    assert!(!archive.verify_integrity());
+
+    let limits = Limits {
+        max_entries: 2,
+        ..Default::default()
+    };
+    // SAFETY: As for `open`.
+    let result = unsafe { MmapArchive::open_with_limits(file.as_file(), None, &limits) };
+    assert!(result.is_err());
+}
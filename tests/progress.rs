@@ -0,0 +1,126 @@
+use acr::hash::murmur3;
+use hssp2::{metadata, verify_integrity, verify_integrity_with_progress};
+
+const SAMPLES: [&str; 6] = [
+    "tests/samples/wfld-normal.hssp",
+    "tests/samples/wfld-corrupted.hssp",
+    "tests/samples/rfld-multiple.hssp",
+    "tests/samples/rfld-corrupted.hssp",
+    "tests/samples/dhdr-folder.hssp",
+    "tests/samples/dhdr-corrupted.hssp",
+];
+
+#[test]
+fn progress_matches_verify_integrity() {
+    for path in SAMPLES {
+        let archive = std::fs::read(path).unwrap();
+        let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+        let expected = verify_integrity(&mut dh::data::read_ref(&archive), &meta).unwrap();
+
+        for chunk_size in [1, 3, 4, 7, 64, 1 << 20] {
+            let result = verify_integrity_with_progress(
+                &mut dh::data::read_ref(&archive),
+                &meta,
+                chunk_size,
+                &mut |_, _| true,
+            )
+            .unwrap();
+            assert_eq!(result, Some(expected), "{} ({})", path, chunk_size);
+        }
+    }
+}
+
+#[test]
+fn progress_matches_murmur3() {
+    // Every tail length, and chunks that split the 4 byte blocks.
+    for length in 0..=67 {
+        let mut archive = vec![0; 64];
+        archive[..4].copy_from_slice(b"HSSP");
+        archive.extend((0..length).map(|i| (i * 37 + 11) as u8));
+        let hash = murmur3(&mut dh::data::read_ref(&archive), 64, length, 0x31082007).unwrap();
+        archive[4..8].copy_from_slice(&hash.to_le_bytes());
+
+        let meta = hssp2::Metadata {
+            version: 2,
+            checksum: hash,
+            encryption: None,
+            files: vec![],
+            main_file: None,
+            extensions: None,
+            decompressed: None,
+        };
+        for chunk_size in [1, 2, 3, 5, 6, 9, 16] {
+            let result = verify_integrity_with_progress(
+                &mut dh::data::read_ref(&archive),
+                &meta,
+                chunk_size,
+                &mut |_, _| true,
+            )
+            .unwrap();
+            assert_eq!(result, Some(true), "{} ({})", length, chunk_size);
+        }
+    }
+}
+
+#[test]
+fn progress_reports() {
+    let archive = std::fs::read("tests/samples/wfld-multiple.hssp").unwrap();
+    let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+    let total = archive.len() as u64 - 64;
+
+    let mut reports = Vec::new();
+    let result = verify_integrity_with_progress(
+        &mut dh::data::read_ref(&archive),
+        &meta,
+        16,
+        &mut |processed, size| {
+            assert_eq!(size, total);
+            reports.push(processed);
+            true
+        },
+    )
+    .unwrap();
+
+    assert_eq!(result, Some(true));
+    assert_eq!(reports.len() as u64, total.div_ceil(16));
+    assert!(reports[..reports.len() - 1]
+        .iter()
+        .enumerate()
+        .all(|(i, &processed)| processed == 16 * (i as u64 + 1)));
+    assert_eq!(reports.last(), Some(&total));
+}
+
+#[test]
+fn progress_cancel() {
+    let archive = std::fs::read("tests/samples/wfld-multiple.hssp").unwrap();
+    let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+
+    let mut calls = 0;
+    let result = verify_integrity_with_progress(
+        &mut dh::data::read_ref(&archive),
+        &meta,
+        16,
+        &mut |processed, _| {
+            calls += 1;
+            processed < 32
+        },
+    )
+    .unwrap();
+
+    assert_eq!(result, None);
+    assert_eq!(calls, 2);
+}
+
+#[test]
+fn progress_zero_chunk_size() {
+    let archive = std::fs::read("tests/samples/wfld-normal.hssp").unwrap();
+    let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+
+    assert!(verify_integrity_with_progress(
+        &mut dh::data::read_ref(&archive),
+        &meta,
+        0,
+        &mut |_, _| true,
+    )
+    .is_err());
+}
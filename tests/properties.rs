@@ -0,0 +1,121 @@
+mod common;
+
+use common::read_meta_with;
+use hssp2::{append, create_extended, write_hash, Entry, EntryWithSource, Extensions, File};
+use std::{collections::BTreeMap, io::ErrorKind};
+
+fn create(
+    extensions: &Extensions,
+    encryption: Option<(&str, &[u8; 16])>,
+) -> std::io::Result<Vec<u8>> {
+    let file = Entry::from(File {
+        path: "a.txt".to_string(),
+        length: 1,
+        ..Default::default()
+    });
+    let mut target = dh::data::rw_empty();
+    let result = create_extended(
+        3,
+        vec![EntryWithSource(&file, &mut dh::data::read_ref(b"a"))],
+        encryption,
+        None,
+        Some(extensions),
+        &mut target,
+        1024,
+    )?;
+    write_hash(&mut target, result)?;
+    Ok(dh::data::close(target))
+}
+
+fn release() -> Extensions {
+    Extensions {
+        comment: Some("Nightly build".to_string()),
+        properties: BTreeMap::from([
+            ("build-id".to_string(), "1234".to_string()),
+            ("creator".to_string(), "release-bot".to_string()),
+            ("git-hash".to_string(), "0b38023".to_string()),
+        ]),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn properties_roundtrip() {
+    let archive = create(&release(), None).unwrap();
+    let meta = read_meta_with(&archive, None);
+    assert_eq!(meta.comment(), Some("Nightly build"));
+    assert_eq!(meta.properties(), &release().properties);
+    assert_eq!(meta.properties()["build-id"], "1234");
+
+    // Appending rewrites the extension section, which keeps both.
+    let mut target = dh::data::rw(archive);
+    let file = Entry::from(File {
+        path: "b.txt".to_string(),
+        length: 1,
+        ..Default::default()
+    });
+    append(
+        &mut target,
+        vec![EntryWithSource(&file, &mut dh::data::read_ref(b"b"))],
+        1024,
+    )
+    .unwrap();
+    let meta = read_meta_with(&dh::data::close(target), None);
+    assert_eq!(meta.files.len(), 2);
+    assert_eq!(meta.comment(), Some("Nightly build"));
+    assert_eq!(meta.properties(), &release().properties);
+}
+
+#[test]
+fn properties_encrypted() {
+    let archive = create(&release(), Some(("password", &[7; 16]))).unwrap();
+
+    // Like the rest of the extension section, they are readable without the
+    // password.
+    let meta = read_meta_with(&archive, None);
+    assert!(meta.files.is_empty());
+    assert_eq!(meta.comment(), Some("Nightly build"));
+    assert_eq!(meta.properties()["creator"], "release-bot");
+
+    let meta = read_meta_with(&archive, Some("password"));
+    assert_eq!(meta.files.len(), 1);
+    assert_eq!(meta.properties(), &release().properties);
+}
+
+#[test]
+fn properties_empty() {
+    let archive = create(
+        &Extensions {
+            comment: Some(String::new()),
+            properties: BTreeMap::from([(String::new(), String::new())]),
+            ..Default::default()
+        },
+        None,
+    )
+    .unwrap();
+    let meta = read_meta_with(&archive, None);
+    assert_eq!(meta.comment(), Some(""));
+    assert_eq!(meta.properties()[""], "");
+
+    let meta = read_meta_with(&create(&Extensions::default(), None).unwrap(), None);
+    assert_eq!(meta.comment(), None);
+    assert!(meta.properties().is_empty());
+
+    let archive = std::fs::read("tests/samples/dhdr-normal.hssp").unwrap();
+    let meta = read_meta_with(&archive, None);
+    assert_eq!(meta.comment(), None);
+    assert!(meta.properties().is_empty());
+}
+
+#[test]
+fn properties_key_too_long() {
+    let error = create(
+        &Extensions {
+            properties: BTreeMap::from([("k".repeat(65536), String::new())]),
+            ..Default::default()
+        },
+        None,
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
@@ -0,0 +1,161 @@
+use dh::recommended::*;
+use hssp2::{
+    create_extended, extract, metadata, recover, repair, verify_integrity, write_hash, Attributes,
+    Entry, EntryWithSource, File, Link,
+};
+
+#[test]
+fn recover_intact() {
+    let mut reader = dh::file::open_r("tests/samples/dhdr-withmain.hssp").unwrap();
+    let recovery = recover(&mut reader).unwrap();
+
+    assert_eq!(recovery.version, 3);
+    assert_eq!(recovery.files.len(), 1);
+    assert_eq!(recovery.files[0].path, "test.txt");
+    assert_eq!(recovery.files[0].offset, 146);
+    assert_eq!(recovery.files[0].length, 13);
+    assert_eq!(recovery.main_file, Some(0));
+    assert!(recovery.damaged.is_empty());
+}
+
+#[test]
+fn recover_folder() {
+    let mut reader = dh::file::open_r("tests/samples/rfld-folder.hssp").unwrap();
+    let recovery = recover(&mut reader).unwrap();
+
+    assert_eq!(recovery.version, 2);
+    assert_eq!(recovery.files.len(), 2);
+    assert_eq!(recovery.files[0].path, "test");
+    assert!(recovery.files[0].directory);
+    assert_eq!(recovery.files[1].path, "test/test.txt");
+    assert!(!recovery.files[1].directory);
+}
+
+#[test]
+fn recover_truncated() {
+    let archive = std::fs::read("tests/samples/wfld-multiple.hssp").unwrap();
+    let archive = &archive[..archive.len() - 4];
+    let recovery = recover(&mut dh::data::read_ref(archive)).unwrap();
+
+    assert_eq!(recovery.files.len(), 1);
+    assert_eq!(recovery.files[0].path, "test.txt");
+    assert_eq!(recovery.damaged, vec![(103, 39)]);
+}
+
+#[test]
+fn recover_corrupted_length() {
+    let mut archive = std::fs::read("tests/samples/wfld-multiple.hssp").unwrap();
+    // Make the first entry's path length point past its padding.
+    archive[72] = 0xff;
+    assert!(metadata(&mut dh::data::read_ref(&archive), None).is_err());
+
+    let recovery = recover(&mut dh::data::read_ref(&archive)).unwrap();
+    assert_eq!(recovery.files.len(), 1);
+    assert_eq!(recovery.files[0].path, "test2.txt");
+    assert_eq!(recovery.damaged, vec![(64, 39)]);
+
+    let mut target = dh::data::rw_empty();
+    let result = repair(
+        &mut dh::data::read_ref(&archive),
+        &recovery,
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+
+    let mut reader = target;
+    reader.rewind().unwrap();
+    let meta = metadata(&mut reader, None).unwrap();
+    assert!(verify_integrity(&mut reader, &meta).unwrap());
+    assert_eq!(meta.version, 1);
+    assert_eq!(meta.files.len(), 1);
+    assert_eq!(meta.files[0].path, "test2.txt");
+
+    let mut target = dh::data::write_new(meta.files[0].length);
+    extract(&mut reader, &meta.files[0], &mut target, 1024, 0).unwrap();
+    assert_eq!(dh::data::close(target), b"Hello, world! 2");
+}
+
+#[test]
+fn recover_attributes_and_links() {
+    let attributes = Some(Attributes {
+        mtime: 1_000_000_000,
+        mode: 0o600,
+        ..Default::default()
+    });
+    let files = [
+        Entry {
+            attributes,
+            ..Entry::from(File {
+                path: "a.txt".to_string(),
+                length: 1,
+                ..Default::default()
+            })
+        },
+        Entry {
+            link: Some(Link::Symbolic("a.txt".to_string())),
+            ..Entry::from(File {
+                path: "link".to_string(),
+                ..Default::default()
+            })
+        },
+        Entry {
+            attributes,
+            ..Entry::from(File {
+                path: "b.txt".to_string(),
+                length: 1,
+                ..Default::default()
+            })
+        },
+    ];
+    let mut readers: Vec<_> = files.iter().map(|_| dh::data::read_ref(b"a")).collect();
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| EntryWithSource(file, reader))
+        .collect();
+    let mut target = dh::data::rw_empty();
+    let result = create_extended(3, sources, None, Some(2), None, &mut target, 1024).unwrap();
+    write_hash(&mut target, result).unwrap();
+    let mut archive = dh::data::close(target);
+
+    let recovery = recover(&mut dh::data::read_ref(&archive)).unwrap();
+    assert_eq!(recovery.files[0].attributes, attributes);
+    assert_eq!(recovery.files[1].link, files[1].link);
+    assert_eq!(recovery.files[2].attributes, attributes);
+    assert_eq!(recovery.dropped, 0);
+
+    let mut target = dh::data::rw_empty();
+    let result = repair(
+        &mut dh::data::read_ref(&archive),
+        &recovery,
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+    let mut reader = target;
+    let meta = metadata(&mut reader, None).unwrap();
+    assert_eq!(meta.files[1].link, files[1].link);
+    assert_eq!(meta.files[2].attributes, attributes);
+    assert_eq!(meta.main_file, Some(2));
+
+    // Corrupt the path length of the link, after which entry indices are
+    // unknown.
+    archive[128 + 21 + 8] = 0xff;
+    let recovery = recover(&mut dh::data::read_ref(&archive)).unwrap();
+    let paths: Vec<_> = recovery.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, ["a.txt", "b.txt"]);
+    assert_eq!(recovery.files[0].attributes, attributes);
+    assert_eq!(recovery.files[1].attributes, None);
+    assert_eq!(recovery.dropped, 2);
+    assert_eq!(recovery.main_file, None);
+}
+
+#[test]
+fn recover_encrypted() {
+    let mut reader = dh::file::open_r("tests/samples/wfld-encrypted.hssp").unwrap();
+
+    assert!(recover(&mut reader).is_err());
+}
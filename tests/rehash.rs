@@ -0,0 +1,67 @@
+use hssp2::{metadata, rehash, verify_integrity};
+
+fn rehash_sample(path: &str, dry_run: bool) -> (Vec<u8>, (u32, u32)) {
+    let mut target = dh::data::rw(std::fs::read(path).unwrap());
+    let result = rehash(&mut target, dry_run).unwrap();
+    (dh::data::close(target), result)
+}
+
+fn is_intact(archive: &[u8]) -> bool {
+    let meta = metadata(&mut dh::data::read_ref(archive), None).unwrap();
+    verify_integrity(&mut dh::data::read_ref(archive), &meta).unwrap()
+}
+
+#[test]
+fn rehash_intact() {
+    for path in [
+        "tests/samples/wfld-normal.hssp",
+        "tests/samples/rfld-normal.hssp",
+        "tests/samples/dhdr-normal.hssp",
+    ] {
+        let original = std::fs::read(path).unwrap();
+        let (archive, (old, new)) = rehash_sample(path, false);
+
+        assert_eq!(old, new, "{}", path);
+        assert_eq!(archive, original, "{}", path);
+    }
+}
+
+#[test]
+fn rehash_corrupted_dry_run() {
+    for path in [
+        "tests/samples/wfld-corrupted.hssp",
+        "tests/samples/rfld-corrupted.hssp",
+        "tests/samples/dhdr-corrupted.hssp",
+    ] {
+        let original = std::fs::read(path).unwrap();
+        let (archive, (old, new)) = rehash_sample(path, true);
+
+        assert_ne!(old, new, "{}", path);
+        assert_eq!(old, u32::from_le_bytes(original[4..8].try_into().unwrap()));
+        assert_eq!(archive, original, "{}", path);
+        assert!(!is_intact(&archive), "{}", path);
+    }
+}
+
+#[test]
+fn rehash_corrupted() {
+    for path in [
+        "tests/samples/wfld-corrupted.hssp",
+        "tests/samples/rfld-corrupted.hssp",
+        "tests/samples/dhdr-corrupted.hssp",
+    ] {
+        let (archive, (_, new)) = rehash_sample(path, false);
+
+        assert_eq!(new, u32::from_le_bytes(archive[4..8].try_into().unwrap()));
+        assert!(is_intact(&archive), "{}", path);
+        // A second pass has nothing left to fix.
+        let mut target = dh::data::rw(archive);
+        assert_eq!(rehash(&mut target, true).unwrap(), (new, new));
+    }
+}
+
+#[test]
+fn rehash_truncated() {
+    let mut target = dh::data::rw(b"HSSP".to_vec());
+    assert!(rehash(&mut target, true).is_err());
+}
@@ -0,0 +1,340 @@
+use hssp2::{
+    create_extended, extract, metadata, verify_detailed, verify_integrity, write_hash, Attributes,
+    Compression, Entry as ArchiveEntry, EntryIntegrity, EntryWithSource, Extensions, File, Kdf,
+    Link,
+};
+use proptest::{
+    collection::{btree_map, vec},
+    option,
+    prelude::*,
+};
+
+#[derive(Debug, Clone)]
+struct Entry {
+    path: String,
+    directory: bool,
+    data: Vec<u8>,
+    compression: Compression,
+    attributes: Option<Attributes>,
+    link: Option<Link>,
+}
+
+#[derive(Debug, Clone)]
+struct Archive {
+    version: u8,
+    entries: Vec<Entry>,
+    encryption: Option<(String, [u8; 16])>,
+    main_file: Option<u32>,
+    extensions: Option<Extensions>,
+}
+
+fn compression() -> impl Strategy<Value = Compression> {
+    proptest::sample::select(vec![
+        Compression::None,
+        #[cfg(feature = "deflate")]
+        Compression::Deflate,
+        #[cfg(feature = "zstd")]
+        Compression::Zstd,
+        #[cfg(feature = "lzma")]
+        Compression::Lzma,
+    ])
+}
+
+fn link() -> impl Strategy<Value = Option<Link>> {
+    option::of(("\\PC{1,24}", any::<bool>()).prop_map(|(target, hard)| {
+        if hard {
+            Link::Hard(target)
+        } else {
+            Link::Symbolic(target)
+        }
+    }))
+}
+
+fn entry(max_size: usize) -> impl Strategy<Value = Entry> {
+    (
+        "\\PC{1,24}",
+        any::<bool>(),
+        vec(any::<u8>(), 0..=max_size),
+        compression(),
+        option::of(any::<(i64, u32, u32, u32)>()),
+        link(),
+    )
+        .prop_filter(
+            "file paths cannot start with //",
+            |(path, directory, _, _, _, _)| *directory || !path.starts_with("//"),
+        )
+        .prop_map(
+            |(path, directory, data, compression, attributes, link)| Entry {
+                path,
+                directory,
+                data: if directory { vec![] } else { data },
+                compression,
+                attributes: attributes.map(|(mtime, mode, uid, gid)| Attributes {
+                    mtime,
+                    mode,
+                    uid,
+                    gid,
+                }),
+                // Directories cannot be links.
+                link: link.filter(|_| !directory),
+            },
+        )
+}
+
+fn extensions() -> impl Strategy<Value = Extensions> {
+    (
+        any::<[u8; 16]>(),
+        any::<bool>(),
+        any::<bool>(),
+        compression(),
+        compression(),
+        any::<bool>(),
+        option::of("\\PC{0,32}"),
+        btree_map("\\PC{0,8}", "\\PC{0,16}", 0..4),
+    )
+        .prop_map(
+            |(
+                salt,
+                authenticated,
+                entry_checksums,
+                compression,
+                body_compression,
+                deduplicate,
+                comment,
+                properties,
+            )| Extensions {
+                kdf: Kdf::Pbkdf2 {
+                    iterations: 1,
+                    salt,
+                },
+                authenticated,
+                entry_checksums,
+                compression,
+                body_compression,
+                deduplicate,
+                comment,
+                properties,
+            },
+        )
+}
+
+fn archive(max_entries: usize, max_size: usize) -> impl Strategy<Value = Archive> {
+    (
+        1u8..=3,
+        vec(entry(max_size), 0..=max_entries),
+        option::of(("\\PC{0,16}", any::<[u8; 16]>())),
+        any::<prop::sample::Index>(),
+        any::<bool>(),
+        option::of(extensions()),
+    )
+        .prop_map(
+            |(version, entries, encryption, main_file, has_main, extensions)| {
+                // Compression, attributes, links and extensions require
+                // version 3.
+                let entries: Vec<_> = entries
+                    .into_iter()
+                    .map(|entry| Entry {
+                        compression: if version == 3 {
+                            entry.compression
+                        } else {
+                            Compression::None
+                        },
+                        attributes: entry.attributes.filter(|_| version == 3),
+                        link: entry.link.filter(|_| version == 3),
+                        ..entry
+                    })
+                    .collect();
+                Archive {
+                    // The main file has to be a regular file.
+                    main_file: {
+                        let files: Vec<_> = (0..entries.len() as u32)
+                            .filter(|&i| {
+                                let entry = &entries[i as usize];
+                                !entry.directory && entry.link.is_none()
+                            })
+                            .collect();
+                        if has_main && !files.is_empty() {
+                            Some(files[main_file.index(files.len())])
+                        } else {
+                            None
+                        }
+                    },
+                    extensions: extensions
+                        .filter(|_| version == 3)
+                        .map(|extensions| Extensions {
+                            // Only encrypted archives can be authenticated.
+                            authenticated: extensions.authenticated && encryption.is_some(),
+                            ..extensions
+                        }),
+                    version,
+                    entries,
+                    encryption,
+                }
+            },
+        )
+}
+
+fn roundtrip(archive: &Archive) -> Result<(), TestCaseError> {
+    let files: Vec<ArchiveEntry> = archive
+        .entries
+        .iter()
+        .map(|e| ArchiveEntry {
+            compression: e.compression,
+            attributes: e.attributes,
+            link: e.link.clone(),
+            ..ArchiveEntry::from(File {
+                path: e.path.clone(),
+                directory: e.directory,
+                offset: 0,
+                length: e.data.len() as u64,
+            })
+        })
+        .collect();
+    let mut readers: Vec<_> = archive
+        .entries
+        .iter()
+        .map(|e| dh::data::read_ref(&e.data))
+        .collect();
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| EntryWithSource(file, reader))
+        .collect();
+    let encryption = archive
+        .encryption
+        .as_ref()
+        .map(|(password, iv)| (password.as_str(), iv));
+
+    let mut target = dh::data::rw_empty();
+    let result = create_extended(
+        archive.version,
+        sources,
+        encryption,
+        archive.main_file,
+        archive.extensions.as_ref(),
+        &mut target,
+        1024,
+    )
+    .unwrap();
+    write_hash(&mut target, result).unwrap();
+    let data = dh::data::close(target);
+
+    let meta = metadata(&mut dh::data::read_ref(&data), encryption.map(|e| e.0)).unwrap();
+    prop_assert!(verify_integrity(&mut dh::data::read_ref(&data), &meta).unwrap());
+    let report = verify_detailed(&mut dh::data::read_ref(&data), &meta).unwrap();
+    prop_assert!(report.checksum);
+    let corrupted = report
+        .entries
+        .iter()
+        .any(|e| matches!(e, EntryIntegrity::Corrupted { .. }));
+    prop_assert!(!corrupted);
+    prop_assert_eq!(meta.version, archive.version);
+    prop_assert_eq!(meta.main_file, archive.main_file);
+    prop_assert_eq!(
+        meta.main_path(),
+        archive
+            .main_file
+            .map(|i| archive.entries[i as usize].path.as_str())
+    );
+    let default_compression = archive
+        .extensions
+        .as_ref()
+        .map(|e| e.compression)
+        .unwrap_or_default();
+    let expected_compression: Vec<_> = archive
+        .entries
+        .iter()
+        .map(|e| match (e.compression, &e.link) {
+            // The target of a link is stored as is.
+            (_, Some(_)) => Compression::None,
+            (Compression::None, None) => default_compression,
+            (compression, None) => compression,
+        })
+        .collect();
+    // The default compression is not stored, neither is the key derivation
+    // of unencrypted archives. Compressed entries, attributes and links
+    // always need an extension section.
+    let expected_extensions = match &archive.extensions {
+        Some(extensions) => Some(Extensions {
+            compression: Compression::None,
+            kdf: if archive.encryption.is_some() {
+                extensions.kdf
+            } else {
+                Kdf::default()
+            },
+            ..extensions.clone()
+        }),
+        None if expected_compression.iter().any(|c| *c != Compression::None)
+            || archive
+                .entries
+                .iter()
+                .any(|e| e.attributes.is_some() || e.link.is_some()) =>
+        {
+            Some(Extensions::default())
+        }
+        None => None,
+    };
+    prop_assert_eq!(&meta.extensions, &expected_extensions);
+    let expected_comment = expected_extensions
+        .as_ref()
+        .and_then(|e| e.comment.as_deref());
+    prop_assert_eq!(meta.comment(), expected_comment);
+    prop_assert_eq!(meta.files.len(), archive.entries.len());
+
+    match &meta.encryption {
+        Some(encryption) => {
+            prop_assert_eq!(encryption.hash, encryption.hash_expected);
+            prop_assert_eq!(&encryption.iv, &archive.encryption.as_ref().unwrap().1);
+        }
+        None => prop_assert!(archive.encryption.is_none()),
+    }
+    let body = meta.body().unwrap_or(&data);
+    for ((file, entry), compression) in meta
+        .files
+        .iter()
+        .zip(&archive.entries)
+        .zip(expected_compression)
+    {
+        prop_assert_eq!(&file.path, &entry.path);
+        prop_assert_eq!(file.directory, entry.directory);
+        prop_assert_eq!(file.compression, compression);
+        prop_assert_eq!(file.attributes, entry.attributes);
+        prop_assert_eq!(&file.link, &entry.link);
+        // The data of a link is its target.
+        let data = match &entry.link {
+            Some(link) => link.target().as_bytes(),
+            None => &entry.data,
+        };
+        prop_assert_eq!(file.uncompressed_length, data.len() as u64);
+        if compression == Compression::None {
+            prop_assert_eq!(file.length, data.len() as u64);
+        }
+
+        let mut target = dh::data::write_new(file.uncompressed_length);
+        extract(&mut dh::data::read_ref(body), file, &mut target, 1024, 0).unwrap();
+        prop_assert_eq!(&dh::data::close(target), data);
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn roundtrip_small(archive in archive(8, 256)) {
+        roundtrip(&archive)?;
+    }
+
+    #[test]
+    fn roundtrip_large_files(archive in archive(2, 16384)) {
+        roundtrip(&archive)?;
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn roundtrip_many_entries(archive in archive(2000, 4)) {
+        roundtrip(&archive)?;
+    }
+}
@@ -0,0 +1,269 @@
+mod common;
+
+use common::read_meta_with;
+use hssp2::{
+    create_extended, prepare_run, run, write_hash, Attributes, Entry, EntryWithSource, File,
+};
+use std::{fs, io::ErrorKind};
+
+fn create_archive(
+    entries: &[(&str, &[u8], Option<u32>)],
+    main_file: Option<u32>,
+    password: Option<&str>,
+) -> Vec<u8> {
+    let files: Vec<Entry> = entries
+        .iter()
+        .map(|(path, data, mode)| Entry {
+            attributes: mode.map(|mode| Attributes {
+                mode,
+                ..Default::default()
+            }),
+            ..Entry::from(File {
+                path: path.trim_end_matches('/').to_string(),
+                directory: path.ends_with('/'),
+                length: data.len() as u64,
+                ..Default::default()
+            })
+        })
+        .collect();
+    let mut readers: Vec<_> = entries
+        .iter()
+        .map(|(_, data, _)| dh::data::read_ref(data))
+        .collect();
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| EntryWithSource(file, reader))
+        .collect();
+    let encryption = password.map(|password| (password, &[1; 16]));
+    let mut target = dh::data::rw_empty();
+    let result =
+        create_extended(3, sources, encryption, main_file, None, &mut target, 1024).unwrap();
+    write_hash(&mut target, result).unwrap();
+    dh::data::close(target)
+}
+
+const SCRIPT: &[u8] =
+    b"#!/bin/sh\necho \"$@\" > \"$OUT\"\ncat data/value.txt >> \"$OUT\"\nexit 3\n";
+
+#[test]
+fn prepare_run_directory() {
+    let archive = create_archive(
+        &[
+            ("bin/run.sh", SCRIPT, None),
+            ("data/value.txt", b"42", None),
+        ],
+        Some(0),
+        None,
+    );
+    let meta = read_meta_with(&archive, None);
+    let directory = tempfile::tempdir().unwrap();
+
+    let launch = prepare_run(
+        &mut dh::data::read_ref(&archive),
+        &meta,
+        Some(directory.path()),
+        1024,
+    )
+    .unwrap();
+    assert!(!launch.temporary);
+    assert_eq!(launch.directory, directory.path());
+    assert_eq!(
+        launch.main_file,
+        directory.path().join("bin").join("run.sh")
+    );
+    assert_eq!(fs::read(&launch.main_file).unwrap(), SCRIPT);
+    assert_eq!(
+        fs::read(directory.path().join("data/value.txt")).unwrap(),
+        b"42"
+    );
+
+    let command = launch.command(&["a", "b"]).unwrap();
+    assert_eq!(command.get_program(), "/bin/sh");
+    assert_eq!(
+        command.get_args().collect::<Vec<_>>(),
+        [launch.main_file.as_os_str(), "a".as_ref(), "b".as_ref()]
+    );
+    assert_eq!(command.get_current_dir(), Some(directory.path()));
+
+    // The given directory is left alone.
+    launch.cleanup().unwrap();
+    assert!(directory.path().join("bin/run.sh").exists());
+}
+
+#[test]
+fn prepare_run_temporary() {
+    let archive = create_archive(&[("main.txt", b"Hello", None)], Some(0), Some("password"));
+    let meta = read_meta_with(&archive, Some("password"));
+
+    let launch = prepare_run(&mut dh::data::read_ref(&archive), &meta, None, 1024).unwrap();
+    assert!(launch.temporary);
+    assert!(launch.main_file.starts_with(&launch.directory));
+    assert_eq!(fs::read(&launch.main_file).unwrap(), b"Hello");
+
+    let directory = launch.directory.clone();
+    launch.cleanup().unwrap();
+    assert!(!directory.exists());
+}
+
+#[test]
+fn prepare_run_read_only() {
+    let archive = create_archive(
+        &[
+            ("main.txt", b"Hello", None),
+            ("locked/", b"", Some(0o555)),
+            ("locked/inner/", b"", Some(0o500)),
+            ("locked/inner/file.txt", b"a", Some(0o444)),
+        ],
+        Some(0),
+        None,
+    );
+    let meta = read_meta_with(&archive, None);
+
+    let launch = prepare_run(&mut dh::data::read_ref(&archive), &meta, None, 1024).unwrap();
+    let directory = launch.directory.clone();
+    assert!(fs::metadata(directory.join("locked"))
+        .unwrap()
+        .permissions()
+        .readonly());
+    launch.cleanup().unwrap();
+    assert!(!directory.exists());
+}
+
+#[test]
+fn prepare_run_invalid() {
+    let archive = create_archive(&[("a.txt", b"a", None)], None, None);
+    let meta = read_meta_with(&archive, None);
+    let error = prepare_run(&mut dh::data::read_ref(&archive), &meta, None, 1024).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+
+    let archive = create_archive(&[("a.txt", b"a", None)], Some(0), Some("password"));
+    for password in [None, Some("wrong")] {
+        let meta = read_meta_with(&archive, password);
+        let error = prepare_run(&mut dh::data::read_ref(&archive), &meta, None, 1024).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn run_script() {
+    let archive = create_archive(
+        &[
+            ("bin/run.sh", SCRIPT, None),
+            ("data/value.txt", b"42", None),
+        ],
+        Some(0),
+        None,
+    );
+    let meta = read_meta_with(&archive, None);
+    let out = tempfile::tempdir().unwrap();
+    let out = out.path().join("out");
+
+    let launch = prepare_run(&mut dh::data::read_ref(&archive), &meta, None, 1024).unwrap();
+    let status = launch
+        .command(&["first", "second"])
+        .unwrap()
+        .env("OUT", &out)
+        .status()
+        .unwrap();
+    launch.cleanup().unwrap();
+    assert_eq!(status.code(), Some(3));
+    assert_eq!(fs::read(&out).unwrap(), b"first second\n42");
+
+    let archive = create_archive(&[("run.sh", b"#!/bin/sh\nexit 3\n", None)], Some(0), None);
+    let meta = read_meta_with(&archive, None);
+    let status = run(&mut dh::data::read_ref(&archive), &meta, &[], 1024).unwrap();
+    assert_eq!(status.code(), Some(3));
+}
+
+#[test]
+fn run_document() {
+    // Documents are opened by another application, which may still need
+    // them after the opener has exited.
+    let archive = create_archive(&[("readme.txt", b"Hello", Some(0o644))], Some(0), None);
+    let meta = read_meta_with(&archive, None);
+    let error = run(&mut dh::data::read_ref(&archive), &meta, &[], 1024).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+}
+
+#[cfg(unix)]
+#[test]
+fn command_kinds() {
+    let archive = create_archive(
+        &[
+            ("tool", b"\x7fELF", Some(0o755)),
+            ("env.sh", b"#!/usr/bin/env  python3 -u \nprint()\n", None),
+            ("readme.txt", b"Hello", Some(0o644)),
+        ],
+        Some(0),
+        None,
+    );
+    let mut meta = read_meta_with(&archive, None);
+    let directory = tempfile::tempdir().unwrap();
+    let mut command = |main_file| {
+        meta.main_file = Some(main_file);
+        let launch = prepare_run(
+            &mut dh::data::read_ref(&archive),
+            &meta,
+            Some(directory.path()),
+            1024,
+        )
+        .unwrap();
+        let command = launch.command(&["x"]).unwrap();
+        let mut parts = vec![command.get_program().to_owned()];
+        parts.extend(command.get_args().map(|arg| arg.to_owned()));
+        parts
+            .iter()
+            .map(|part| {
+                part.to_string_lossy()
+                    .replace(&*directory.path().to_string_lossy(), ".")
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(command(0), ["./tool", "x"]);
+    assert_eq!(command(1), ["/usr/bin/env", "python3 -u", "./env.sh", "x"]);
+    #[cfg(not(target_os = "macos"))]
+    assert_eq!(command(2), ["xdg-open", "./readme.txt"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn cli_run() {
+    let archive = create_archive(
+        &[
+            ("bin/run.sh", SCRIPT, None),
+            ("data/value.txt", b"42", None),
+        ],
+        Some(0),
+        Some("password"),
+    );
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("app.hssp");
+    fs::write(&path, archive).unwrap();
+    let out = directory.path().join("out");
+    let hssp = || {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_hssp"));
+        command.env("OUT", &out);
+        command
+    };
+
+    let status = hssp()
+        .args(["run", "--password", "password"])
+        .arg(&path)
+        .args(["--", "first", "--second"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(3));
+    assert_eq!(fs::read(&out).unwrap(), b"first --second\n42");
+
+    let output = hssp().arg("run").arg(&path).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("decrypted"));
+
+    for args in [&[][..], &["list"], &["run"], &["run", "a.hssp", "b"]] {
+        let output = hssp().args(args).output().unwrap();
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+    }
+}
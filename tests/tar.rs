@@ -0,0 +1,255 @@
+#![cfg(feature = "tar")]
+
+mod common;
+
+use common::{read, read_meta};
+use hssp2::{from_tar, metadata, to_tar, write_hash, Attributes, Link, SkippedEntry, SkippedKind};
+use std::io::Read;
+use tar::{Archive, Builder, EntryType, Header};
+
+fn hssp_to_tar(archive: &[u8], password: Option<&str>) -> Vec<u8> {
+    let meta = metadata(&mut dh::data::read_ref(archive), password).unwrap();
+    let mut target = Vec::new();
+    to_tar(&mut dh::data::read_ref(archive), &meta, &mut target, 1024).unwrap();
+    target
+}
+
+fn tar_to_hssp(tar: &[u8]) -> (Vec<u8>, Vec<SkippedEntry>) {
+    tar_to_hssp_version(tar, 2)
+}
+
+fn tar_to_hssp_version(tar: &[u8], version: u8) -> (Vec<u8>, Vec<SkippedEntry>) {
+    let mut target = dh::data::rw_empty();
+    let (result, skipped) = from_tar(&mut &tar[..], version, &mut target).unwrap();
+    write_hash(&mut target, result).unwrap();
+    (dh::data::close(target), skipped)
+}
+
+fn append(builder: &mut Builder<Vec<u8>>, path: &str, entry_type: EntryType, data: &[u8]) {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, path, data).unwrap();
+}
+
+#[test]
+fn tar_roundtrip() {
+    let archive = std::fs::read("tests/samples/dhdr-folder.hssp").unwrap();
+    let tar = hssp_to_tar(&archive, None);
+
+    let mut reader = Archive::new(&tar[..]);
+    let mut entries = reader.entries().unwrap();
+    let entry = entries.next().unwrap().unwrap();
+    assert!(entry.header().entry_type().is_dir());
+    assert_eq!(&*entry.path_bytes(), b"test/");
+    let mut entry = entries.next().unwrap().unwrap();
+    assert_eq!(&*entry.path_bytes(), b"test/test.txt");
+    let mut data = String::new();
+    entry.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "Hello, world!");
+    assert!(entries.next().is_none());
+
+    let (archive, skipped) = tar_to_hssp(&tar);
+    assert!(skipped.is_empty());
+    let meta = read_meta(&archive);
+    assert_eq!(meta.files.len(), 2);
+    assert_eq!(meta.files[0].path, "test");
+    assert!(meta.files[0].directory);
+    assert_eq!(meta.files[1].path, "test/test.txt");
+    assert_eq!(read(&archive, &meta.files[1]), b"Hello, world!");
+}
+
+#[test]
+fn tar_encrypted() {
+    let archive = std::fs::read("tests/samples/rfld-encrypted.hssp").unwrap();
+    let tar = hssp_to_tar(&archive, Some("Password"));
+
+    let (archive, _) = tar_to_hssp(&tar);
+    let meta = read_meta(&archive);
+    assert_eq!(read(&archive, &meta.files[0]), b"Hello, world!");
+}
+
+#[test]
+fn tar_unrepresentable() {
+    let mut builder = Builder::new(Vec::new());
+    append(&mut builder, "./", EntryType::Directory, b"");
+    append(&mut builder, "./dir/", EntryType::Directory, b"");
+    append(&mut builder, "./dir/a.txt", EntryType::Regular, b"a");
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    builder
+        .append_link(&mut header, "./dir/link", "a.txt")
+        .unwrap();
+    append(&mut builder, "dev/null", EntryType::Char, b"");
+    append(&mut builder, "fifo", EntryType::Fifo, b"");
+    let long_path = "long/".repeat(40) + "file.txt";
+    append(&mut builder, &long_path, EntryType::Regular, &[1; 5000]);
+    let tar = builder.into_inner().unwrap();
+
+    let (archive, skipped) = tar_to_hssp(&tar);
+    assert_eq!(
+        skipped,
+        vec![
+            SkippedEntry {
+                path: "dir/link".to_string(),
+                kind: SkippedKind::Symlink,
+            },
+            SkippedEntry {
+                path: "dev/null".to_string(),
+                kind: SkippedKind::CharacterDevice,
+            },
+            SkippedEntry {
+                path: "fifo".to_string(),
+                kind: SkippedKind::Fifo,
+            },
+        ]
+    );
+
+    let meta = read_meta(&archive);
+    let paths: Vec<_> = meta.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, ["dir", "dir/a.txt", long_path.as_str()]);
+    assert_eq!(read(&archive, &meta.files[1]), b"a");
+    assert_eq!(read(&archive, &meta.files[2]), vec![1; 5000]);
+}
+
+#[test]
+fn tar_links_and_attributes() {
+    let mut builder = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o750);
+    header.set_mtime(1_000_000_000);
+    header.set_uid(1000);
+    header.set_gid(100);
+    builder
+        .append_data(&mut header, "dir/", std::io::empty())
+        .unwrap();
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(1);
+    header.set_mode(0o4755);
+    header.set_mtime(1_500_000_000);
+    builder
+        .append_data(&mut header, "./dir/a.txt", &b"a"[..])
+        .unwrap();
+    for (path, entry_type, target) in [
+        ("dir/link", EntryType::Symlink, "a.txt"),
+        ("./hard", EntryType::Link, "./dir/a.txt"),
+    ] {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_size(0);
+        header.set_mode(0o777);
+        builder.append_link(&mut header, path, target).unwrap();
+    }
+    let tar = builder.into_inner().unwrap();
+
+    let (archive, skipped) = tar_to_hssp_version(&tar, 3);
+    assert!(skipped.is_empty());
+    let meta = read_meta(&archive);
+    let paths: Vec<_> = meta.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, ["dir", "dir/a.txt", "dir/link", "hard"]);
+    assert_eq!(
+        meta.files[0].attributes,
+        Some(Attributes {
+            mtime: 1_000_000_000,
+            mode: 0o750,
+            uid: 1000,
+            gid: 100,
+        })
+    );
+    assert_eq!(meta.files[1].attributes.unwrap().mode, 0o4755);
+    assert_eq!(read(&archive, &meta.files[1]), b"a");
+    assert_eq!(
+        meta.files[2].link,
+        Some(Link::Symbolic("a.txt".to_string()))
+    );
+    assert_eq!(
+        meta.files[3].link,
+        Some(Link::Hard("dir/a.txt".to_string()))
+    );
+
+    let tar = hssp_to_tar(&archive, None);
+    let mut reader = Archive::new(&tar[..]);
+    let entries: Vec<_> = reader
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            let header = entry.header();
+            (
+                String::from_utf8(entry.path_bytes().into_owned()).unwrap(),
+                header.entry_type(),
+                header.mode().unwrap(),
+                header.mtime().unwrap(),
+                header.uid().unwrap(),
+                entry
+                    .link_name_bytes()
+                    .map(|target| String::from_utf8(target.into_owned()).unwrap()),
+            )
+        })
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            (
+                "dir/".to_string(),
+                EntryType::Directory,
+                0o750,
+                1_000_000_000,
+                1000,
+                None
+            ),
+            (
+                "dir/a.txt".to_string(),
+                EntryType::Regular,
+                0o4755,
+                1_500_000_000,
+                0,
+                None
+            ),
+            (
+                "dir/link".to_string(),
+                EntryType::Symlink,
+                0o777,
+                0,
+                0,
+                Some("a.txt".to_string())
+            ),
+            (
+                "hard".to_string(),
+                EntryType::Link,
+                0o777,
+                0,
+                0,
+                Some("dir/a.txt".to_string())
+            ),
+        ]
+    );
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn tar_gz_roundtrip() {
+    use hssp2::{from_tar_gz, to_tar_gz};
+
+    let archive = std::fs::read("tests/samples/wfld-multiple.hssp").unwrap();
+    let meta = metadata(&mut dh::data::read_ref(&archive), None).unwrap();
+    let mut tar_gz = Vec::new();
+    to_tar_gz(&mut dh::data::read_ref(&archive), &meta, &mut tar_gz, 1024).unwrap();
+    assert_eq!(tar_gz[..2], [0x1f, 0x8b]);
+
+    let mut target = dh::data::rw_empty();
+    let (result, skipped) = from_tar_gz(&mut &tar_gz[..], 1, &mut target).unwrap();
+    write_hash(&mut target, result).unwrap();
+    let archive = dh::data::close(target);
+
+    assert!(skipped.is_empty());
+    let meta = read_meta(&archive);
+    assert_eq!(meta.version, 1);
+    assert_eq!(read(&archive, &meta.files[0]), b"Hello, world!");
+    assert_eq!(read(&archive, &meta.files[1]), b"Hello, world! 2");
+}
@@ -0,0 +1,238 @@
+#![cfg(feature = "zip")]
+
+mod common;
+
+use common::{read, read_meta};
+use hssp2::{
+    create_extended, from_zip, metadata, to_zip, write_hash, Attributes, Entry, EntryWithSource,
+    File, Link,
+};
+use std::io::{Cursor, ErrorKind, Read, Write};
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
+
+fn hssp_to_zip(archive: &[u8], password: Option<&str>) -> std::io::Result<Vec<u8>> {
+    let meta = metadata(&mut dh::data::read_ref(archive), password)?;
+    let mut target = dh::data::rw_empty();
+    to_zip(&mut dh::data::read_ref(archive), &meta, &mut target, 1024)?;
+    Ok(dh::data::close(target))
+}
+
+fn zip_to_hssp(zip: &[u8]) -> std::io::Result<Vec<u8>> {
+    zip_to_hssp_version(zip, 3)
+}
+
+fn zip_to_hssp_version(zip: &[u8], version: u8) -> std::io::Result<Vec<u8>> {
+    let mut target = dh::data::rw_empty();
+    let result = from_zip(&mut dh::data::read_ref(zip), version, &mut target)?;
+    write_hash(&mut target, result)?;
+    Ok(dh::data::close(target))
+}
+
+#[test]
+fn zip_directories() {
+    let archive = std::fs::read("tests/samples/rfld-folder.hssp").unwrap();
+    let zip = hssp_to_zip(&archive, None).unwrap();
+
+    let mut reader = ZipArchive::new(Cursor::new(&zip)).unwrap();
+    assert_eq!(reader.len(), 2);
+    assert!(reader.by_index(0).unwrap().is_dir());
+    assert_eq!(reader.by_index(0).unwrap().name(), "test/");
+    let mut data = String::new();
+    reader
+        .by_name("test/test.txt")
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+    assert_eq!(data, "Hello, world!");
+    assert!(reader.comment().is_empty());
+
+    let archive = zip_to_hssp(&zip).unwrap();
+    let meta = read_meta(&archive);
+    assert_eq!(meta.version, 3);
+    assert_eq!(meta.files.len(), 2);
+    assert_eq!(meta.files[0].path, "test");
+    assert!(meta.files[0].directory);
+    assert_eq!(meta.files[1].path, "test/test.txt");
+    assert_eq!(read(&archive, &meta.files[1]), b"Hello, world!");
+    assert_eq!(meta.main_file, None);
+}
+
+#[test]
+fn zip_main_file() {
+    let archive = std::fs::read("tests/samples/dhdr-withmain.hssp").unwrap();
+    let zip = hssp_to_zip(&archive, None).unwrap();
+
+    let reader = ZipArchive::new(Cursor::new(&zip)).unwrap();
+    assert_eq!(reader.comment(), b"hssp-main-file:test.txt");
+
+    let archive = zip_to_hssp(&zip).unwrap();
+    assert_eq!(read_meta(&archive).main_file, Some(0));
+}
+
+#[test]
+fn zip_encrypted() {
+    let archive = std::fs::read("tests/samples/wfld-encrypted.hssp").unwrap();
+
+    let error = hssp_to_zip(&archive, None).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+    let zip = hssp_to_zip(&archive, Some("Password")).unwrap();
+    let archive = zip_to_hssp(&zip).unwrap();
+    let meta = read_meta(&archive);
+    assert_eq!(read(&archive, &meta.files[0]), b"Hello, world!");
+}
+
+#[test]
+fn zip_from_other_tools() {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+    writer.add_directory("dir/", options).unwrap();
+    writer.start_file("dir/a.txt", options).unwrap();
+    writer.write_all(b"a").unwrap();
+    writer.start_file("b.txt", options).unwrap();
+    writer.write_all(&[7; 100000]).unwrap();
+    writer
+        .set_comment("Made elsewhere\nhssp-main-file:b.txt")
+        .unwrap();
+    let zip = writer.finish().unwrap().into_inner();
+
+    let archive = zip_to_hssp(&zip).unwrap();
+    let meta = read_meta(&archive);
+    let paths: Vec<_> = meta.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, ["dir", "dir/a.txt", "b.txt"]);
+    assert_eq!(meta.main_file, Some(2));
+    assert_eq!(read(&archive, &meta.files[1]), b"a");
+    assert_eq!(read(&archive, &meta.files[2]), vec![7; 100000]);
+}
+
+#[test]
+fn zip_missing_main_file() {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    writer
+        .start_file("a.txt", SimpleFileOptions::default())
+        .unwrap();
+    writer.set_comment("hssp-main-file:b.txt").unwrap();
+    let zip = writer.finish().unwrap().into_inner();
+
+    let error = zip_to_hssp(&zip).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn zip_symlink_main_file() {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    writer
+        .start_file("a.txt", SimpleFileOptions::default())
+        .unwrap();
+    writer
+        .add_symlink("link", "a.txt", SimpleFileOptions::default())
+        .unwrap();
+    writer.set_comment("hssp-main-file:link").unwrap();
+    let zip = writer.finish().unwrap().into_inner();
+
+    let error = zip_to_hssp(&zip).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn zip_symlink() {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    writer
+        .add_symlink("link", "target", SimpleFileOptions::default())
+        .unwrap();
+    let zip = writer.finish().unwrap().into_inner();
+
+    let error = zip_to_hssp_version(&zip, 2).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+    let archive = zip_to_hssp(&zip).unwrap();
+    let meta = read_meta(&archive);
+    assert_eq!(
+        meta.files[0].link,
+        Some(Link::Symbolic("target".to_string()))
+    );
+    assert_eq!(meta.files[0].attributes.unwrap().mode, 0o777);
+}
+
+#[test]
+fn zip_links_and_attributes() {
+    let attributes = |mode| {
+        Some(Attributes {
+            mtime: 1_500_000_000,
+            mode,
+            ..Default::default()
+        })
+    };
+    let files = [
+        Entry {
+            attributes: attributes(0o750),
+            ..Entry::from(File {
+                path: "dir".to_string(),
+                directory: true,
+                ..Default::default()
+            })
+        },
+        Entry {
+            attributes: attributes(0o640),
+            ..Entry::from(File {
+                path: "dir/a.txt".to_string(),
+                length: 1,
+                ..Default::default()
+            })
+        },
+        Entry {
+            link: Some(Link::Symbolic("a.txt".to_string())),
+            ..Entry::from(File {
+                path: "dir/link".to_string(),
+                ..Default::default()
+            })
+        },
+        Entry {
+            link: Some(Link::Hard("dir/a.txt".to_string())),
+            ..Entry::from(File {
+                path: "hard".to_string(),
+                ..Default::default()
+            })
+        },
+    ];
+    let mut readers: Vec<_> = files.iter().map(|_| dh::data::read_ref(b"a")).collect();
+    let sources = files
+        .iter()
+        .zip(readers.iter_mut())
+        .map(|(file, reader)| EntryWithSource(file, reader))
+        .collect();
+    let mut target = dh::data::rw_empty();
+    let result = create_extended(3, sources, None, None, None, &mut target, 1024).unwrap();
+    write_hash(&mut target, result).unwrap();
+    let archive = dh::data::close(target);
+
+    let zip = hssp_to_zip(&archive, None).unwrap();
+    let mut reader = ZipArchive::new(Cursor::new(&zip)).unwrap();
+    let file = reader.by_name("dir/a.txt").unwrap();
+    assert_eq!(file.unix_mode().unwrap() & 0o7777, 0o640);
+    assert_eq!(
+        file.last_modified().unwrap().to_string(),
+        "2017-07-14 02:40:00"
+    );
+    drop(file);
+    assert!(reader.by_name("dir/link").unwrap().is_symlink());
+    let mut data = Vec::new();
+    let mut hard = reader.by_name("hard").unwrap();
+    assert!(hard.is_file());
+    hard.read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"a");
+    drop(hard);
+
+    let archive = zip_to_hssp(&zip).unwrap();
+    let meta = read_meta(&archive);
+    let paths: Vec<_> = meta.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, ["dir", "dir/a.txt", "dir/link", "hard"]);
+    assert_eq!(meta.files[0].attributes, attributes(0o750));
+    assert_eq!(meta.files[1].attributes, attributes(0o640));
+    assert_eq!(
+        meta.files[2].link,
+        Some(Link::Symbolic("a.txt".to_string()))
+    );
+    assert_eq!(meta.files[3].link, None);
+    assert_eq!(read(&archive, &meta.files[3]), b"a");
+}